@@ -4,7 +4,12 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use wynd_invest::msg::{
-    ConfigResponse, ExecuteMsg, InfoResponse, InstantiateMsg, ListInvestmentsResponse, QueryMsg,
+    AllNftInfoResponse, CapacityResponse, ConfigResponse, ContractInfoResponse, ExecuteMsg,
+    InfoResponse, InstantiateMsg, InvestmentInfoResponse, ListInvestmentsResponse,
+    ListLocationsResponse, NftInfoResponse, NumTokensResponse, OperatorsResponse,
+    OracleKeyResponse, OwnerOfResponse, PauseStatusResponse, PendingOracleResponse,
+    PortfolioResponse, QueryMsg, SimulateInvestResponse, SimulateWithdrawResponse,
+    SolvencyResponse, TokensResponse,
 };
 
 fn main() {
@@ -18,5 +23,22 @@ fn main() {
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(ConfigResponse), &out_dir);
     export_schema(&schema_for!(ListInvestmentsResponse), &out_dir);
+    export_schema(&schema_for!(InvestmentInfoResponse), &out_dir);
+    export_schema(&schema_for!(ListLocationsResponse), &out_dir);
     export_schema(&schema_for!(InfoResponse), &out_dir);
+    export_schema(&schema_for!(CapacityResponse), &out_dir);
+    export_schema(&schema_for!(SolvencyResponse), &out_dir);
+    export_schema(&schema_for!(PauseStatusResponse), &out_dir);
+    export_schema(&schema_for!(OracleKeyResponse), &out_dir);
+    export_schema(&schema_for!(PendingOracleResponse), &out_dir);
+    export_schema(&schema_for!(PortfolioResponse), &out_dir);
+    export_schema(&schema_for!(SimulateInvestResponse), &out_dir);
+    export_schema(&schema_for!(SimulateWithdrawResponse), &out_dir);
+    export_schema(&schema_for!(OwnerOfResponse), &out_dir);
+    export_schema(&schema_for!(OperatorsResponse), &out_dir);
+    export_schema(&schema_for!(NumTokensResponse), &out_dir);
+    export_schema(&schema_for!(ContractInfoResponse), &out_dir);
+    export_schema(&schema_for!(NftInfoResponse), &out_dir);
+    export_schema(&schema_for!(AllNftInfoResponse), &out_dir);
+    export_schema(&schema_for!(TokensResponse), &out_dir);
 }
@@ -1,24 +1,49 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    ensure_eq, from_slice, to_binary, Addr, Binary, Deps, DepsMut, Env, Event, MessageInfo, Order,
-    Response, StdError, StdResult, Uint128,
+    coins, ensure_eq, from_slice, to_binary, Addr, BankMsg, Binary, CosmosMsg, Decimal, Deps,
+    DepsMut, Env, Event, MessageInfo, Order, Response, StdResult, Storage, Uint128,
 };
+use cw0::{one_coin, Expiration};
 use cw2::{get_contract_version, set_contract_version};
-use cw20::{Cw20CoinVerified, Cw20Contract, Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw20::{Cw20Contract, Cw20ExecuteMsg, Cw20ReceiveMsg, Denom};
+use cw_storage_plus::Bound;
+use semver::Version;
 
 use crate::error::ContractError;
+use crate::migration::upgrades_between;
 use crate::msg::{
-    ConfigResponse, ExecuteMsg, InfoResponse, InstantiateMsg, InvestmentResponse,
-    ListInvestmentsResponse, MigrateMsg, OracleValues, QueryMsg, ReceiveMsg,
+    signed_payload, CapacityResponse, ConfigResponse, ExecuteMsg, InfoResponse, InstantiateMsg,
+    InvestmentInfoResponse, InvestmentResponse, ListInvestmentsResponse, ListLocationsResponse,
+    LocationResponse, MigrateMsg, OracleKeyResponse, OracleValues, PendingOracleResponse,
+    PortfolioResponse, PortfolioTotals, QueryMsg, ReceiveMsg, RegisteredKey,
+    SimulateInvestResponse, SimulateWithdrawResponse, SimulatedWithdrawal, SolvencyResponse,
+    TokenAmount, TokenSolvency,
 };
+use crate::nft;
+use crate::payout::checked_mul;
 use crate::r3::validate_r3;
-use crate::state::{Config, Investment, Location, Measurement, CONFIG, INVESTMENTS, LOCATIONS};
+use crate::state::{
+    count_investments, default_quorum, denom_name, investments, measurement_after, median,
+    next_investment_id, Config, EarlyExit, Investment, Location, Measurement, OracleGuards,
+    OracleKey, PauseFlags, PendingOracle, Reserve, Submission, ValueRange, CONFIG, LOCATIONS,
+    MEASUREMENTS, ORACLE_KEYS, ORACLE_NONCES, PAUSED, PENDING_ORACLES, SUBMISSIONS,
+};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:wynd-invest";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// how long a proposed oracle has to accept, unless given
+const HANDOVER_TIME: u64 = 7 * 86400;
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -28,18 +53,81 @@ pub fn instantiate(
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
+    if msg.tokens.is_empty() {
+        return Err(ContractError::NoTokens);
+    }
+    if msg.max_payout < Decimal::one() {
+        return Err(ContractError::InvalidMaxPayout);
+    }
+    msg.payout_curve.validate()?;
+    let early_exit = match msg.early_exit_penalty {
+        Some(penalty) if penalty > Decimal::one() => return Err(ContractError::InvalidPenalty),
+        Some(penalty) => Some(EarlyExit {
+            penalty,
+            fee_collector: msg
+                .fee_collector
+                .map(|addr| deps.api.addr_validate(&addr))
+                .transpose()?,
+        }),
+        None => None,
+    };
+    let mut maturity_tiers = msg.maturity_tiers;
+    maturity_tiers.push(msg.maturity_days);
+    maturity_tiers.sort_unstable();
+    maturity_tiers.dedup();
+    if maturity_tiers[0] == 0 {
+        return Err(ContractError::InvalidMaturity(0));
+    }
+    let tokens = msg
+        .tokens
+        .into_iter()
+        .map(|token| match token {
+            Denom::Native(denom) => Ok(Denom::Native(denom)),
+            Denom::Cw20(addr) => Ok(Denom::Cw20(deps.api.addr_validate(addr.as_str())?)),
+        })
+        .collect::<StdResult<_>>()?;
+
     let config = Config {
+        admin: msg
+            .admin
+            .map(|admin| deps.api.addr_validate(&admin))
+            .transpose()?,
+        pauser: msg
+            .pauser
+            .map(|pauser| deps.api.addr_validate(&pauser))
+            .transpose()?,
         oracle: deps.api.addr_validate(&msg.oracle)?,
-        token: deps.api.addr_validate(&msg.token)?,
+        oracles: msg
+            .oracles
+            .iter()
+            .map(|oracle| deps.api.addr_validate(oracle))
+            .collect::<StdResult<_>>()?,
+        quorum: msg.quorum.unwrap_or_else(default_quorum),
+        oracle_guards: msg.oracle_guards,
+        resolutions: msg.resolutions,
+        tokens,
         max_investment_hex: msg.max_investment_hex,
         maturity_days: msg.maturity_days,
+        maturity_tiers,
         measurement_window: msg.measurement_window,
+        max_payout: msg.max_payout,
+        payout_curve: msg.payout_curve,
+        index_direction: msg.index_direction,
+        early_exit,
     };
+    config.validate_oracles()?;
+    config.resolutions.validate()?;
     CONFIG.save(deps.storage, &config)?;
 
+    for RegisteredKey { oracle, key } in msg.oracle_keys.into_iter() {
+        let oracle = deps.api.addr_validate(&oracle)?;
+        register_oracle_key(deps.storage, &config, &oracle, &key)?;
+    }
+
     let empty_hex = Location::default();
     for index in msg.locations.into_iter() {
         let hex = validate_r3(index)?;
+        config.resolutions.check(&hex)?;
         LOCATIONS.save(deps.storage, &hex, &empty_hex)?;
     }
 
@@ -55,9 +143,60 @@ pub fn execute(
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::Receive(msg) => receive(deps, env, info, msg),
-        ExecuteMsg::Withdraw {} => withdraw(deps, env, info),
+        ExecuteMsg::Invest { hex, maturity_days } => {
+            invest_native(deps, env, info, hex, maturity_days)
+        }
+        ExecuteMsg::FundReserve {} => fund_reserve_native(deps, info),
+        ExecuteMsg::Withdraw {
+            hexes,
+            ids,
+            limit,
+            recipient,
+            msg,
+        } => withdraw(deps, env, info, hexes, ids, limit, recipient, msg),
+        ExecuteMsg::ExitEarly { id } => exit_early(deps, env, info, id),
         ExecuteMsg::StoreOracle { values } => store_oracle(deps, env, info, values),
-        ExecuteMsg::UpdateOracle { oracle } => update_oracle(deps, env, info, oracle),
+        ExecuteMsg::StoreSignedOracle {
+            oracle,
+            nonce,
+            values,
+            signature,
+        } => store_signed_oracle(deps, env, oracle, nonce, values, signature),
+        ExecuteMsg::ProposeOracle { oracle, expires } => {
+            propose_oracle(deps, env, info, oracle, expires)
+        }
+        ExecuteMsg::AcceptOracle {} => accept_oracle(deps, env, info),
+        ExecuteMsg::CancelOracle {} => cancel_oracle(deps, info),
+        ExecuteMsg::SetOracleKey { oracle, key } => set_oracle_key(deps, info, oracle, key),
+        ExecuteMsg::AddLocations { locations } => add_locations(deps, info, locations),
+        ExecuteMsg::RetireLocation { hex } => retire_location(deps, info, hex),
+        ExecuteMsg::ReopenLocation { hex } => reopen_location(deps, info, hex),
+        ExecuteMsg::SetOracleGuards { guards } => set_oracle_guards(deps, info, guards),
+        ExecuteMsg::SetValueRange { hex, range } => set_value_range(deps, info, hex, range),
+        ExecuteMsg::SetPause {
+            investments,
+            oracle,
+            withdrawals,
+        } => set_pause(deps, info, investments, oracle, withdrawals),
+        ExecuteMsg::TransferNft {
+            recipient,
+            token_id,
+        } => nft::transfer_nft(deps, env, info, recipient, token_id),
+        ExecuteMsg::SendNft {
+            contract,
+            token_id,
+            msg,
+        } => nft::send_nft(deps, env, info, contract, token_id, msg),
+        ExecuteMsg::Approve {
+            spender,
+            token_id,
+            expires,
+        } => nft::approve(deps, env, info, spender, token_id, expires),
+        ExecuteMsg::Revoke { spender, token_id } => nft::revoke(deps, env, info, spender, token_id),
+        ExecuteMsg::ApproveAll { operator, expires } => {
+            nft::approve_all(deps, env, info, operator, expires)
+        }
+        ExecuteMsg::RevokeAll { operator } => nft::revoke_all(deps, info, operator),
     }
 }
 
@@ -67,159 +206,613 @@ pub fn receive(
     info: MessageInfo,
     wrapper: Cw20ReceiveMsg,
 ) -> Result<Response, ContractError> {
-    let coin = Cw20CoinVerified {
-        address: info.sender,
-        amount: wrapper.amount,
-    };
+    let token = Denom::Cw20(info.sender);
     let sender = deps.api.addr_validate(&wrapper.sender)?;
     let msg: ReceiveMsg = from_slice(&wrapper.msg)?;
 
     match msg {
-        ReceiveMsg::Invest { hex } => invest(deps, env, sender, coin, hex),
+        ReceiveMsg::Invest { hex, maturity_days } => {
+            invest(deps, env, sender, token, wrapper.amount, hex, maturity_days)
+        }
+        ReceiveMsg::FundReserve {} => fund_reserve(deps, sender, token, wrapper.amount),
+    }
+}
+
+pub fn fund_reserve_native(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let coin = one_coin(&info)?;
+    let token = Denom::Native(coin.denom);
+    fund_reserve(deps, info.sender, token, coin.amount)
+}
+
+pub fn fund_reserve(
+    deps: DepsMut,
+    sender: Addr,
+    token: Denom,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.tokens.contains(&token) {
+        return Err(ContractError::InvalidToken(denom_name(&token)));
     }
+
+    let mut reserve = Reserve::load(deps.storage, &token)?;
+    reserve.reserve += amount;
+    reserve.save(deps.storage)?;
+
+    let evt = Event::new("fund-reserve")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("token", denom_name(&token))
+        .add_attribute("reserve", reserve.reserve.to_string())
+        .add_attribute("sender", sender);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn invest_native(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    hex: String,
+    maturity_days: Option<u64>,
+) -> Result<Response, ContractError> {
+    let coin = one_coin(&info)?;
+    let token = Denom::Native(coin.denom);
+    invest(
+        deps,
+        env,
+        info.sender,
+        token,
+        coin.amount,
+        hex,
+        maturity_days,
+    )
 }
 
 pub fn invest(
     deps: DepsMut,
     env: Env,
     sender: Addr,
-    coin: Cw20CoinVerified,
+    token: Denom,
+    amount: Uint128,
     hex: String,
+    maturity_days: Option<u64>,
 ) -> Result<Response, ContractError> {
+    let checked = check_invest(deps.as_ref(), &env, &token, amount, hex, maturity_days)?;
+    let hex = checked.hex;
+    LOCATIONS.save(deps.storage, &hex, &checked.location)?;
+    checked.reserve.save(deps.storage)?;
+
+    let id = next_investment_id(deps.storage)?;
+    let invest = Investment {
+        id,
+        investor: sender.clone(),
+        hex: hex.clone(),
+        token: token.clone(),
+        amount,
+        baseline_index: checked.baseline.value,
+        invested_time: env.block.time.seconds(),
+        maturity_time: checked.maturity_time,
+        approvals: vec![],
+    };
+    investments().save(deps.storage, id.into(), &invest)?;
+    count_investments(deps.storage, 1, 0)?;
+
+    let evt = Event::new("invest")
+        .add_attribute("investment_id", id.to_string())
+        .add_attribute("index", hex)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("token", denom_name(&token))
+        .add_attribute("maturity_days", checked.maturity_days.to_string())
+        .add_attribute("investor", sender);
+    Ok(Response::new().add_event(evt))
+}
+
+/// An investment that passed all checks, along with the state it changes
+struct CheckedInvestment {
+    hex: String,
+    maturity_days: u64,
+    maturity_time: u64,
+    // latest measurement, locked in as baseline
+    baseline: Measurement,
+    // location and reserve including this investment
+    location: Location,
+    reserve: Reserve,
+}
+
+/// Runs all checks of a new investment without writing state,
+/// shared by `invest` and the `SimulateInvest` query
+fn check_invest(
+    deps: Deps,
+    env: &Env,
+    token: &Denom,
+    amount: Uint128,
+    hex: String,
+    maturity_days: Option<u64>,
+) -> Result<CheckedInvestment, ContractError> {
+    PauseFlags::load(deps.storage)?.assert_investments()?;
     let config = CONFIG.load(deps.storage)?;
-    if config.token != coin.address {
-        return Err(ContractError::InvalidToken(coin.address.into()));
+    if !config.tokens.contains(token) {
+        return Err(ContractError::InvalidToken(denom_name(token)));
     }
+    let maturity_days = config.maturity_for(maturity_days)?;
 
     let hex = validate_r3(hex)?;
-    let invested = env.block.time.seconds();
-    let maturity_date = invested + config.maturity_days * 86400;
+    let maturity_time = env.block.time.seconds() + maturity_days * 86400;
 
     // update investment info in Location
-    let mut location = LOCATIONS.load(deps.storage, &hex)?;
-    location.add_investment(coin.amount);
-    LOCATIONS.save(deps.storage, &hex, &location)?;
+    let mut location = LOCATIONS
+        .may_load(deps.storage, &hex)?
+        .ok_or_else(|| ContractError::UnregisteredLocation(hex.clone()))?;
+    if location.retired {
+        return Err(ContractError::LocationRetired(hex));
+    }
+    let remaining = location.remaining_capacity(&config);
+    if amount > remaining {
+        return Err(ContractError::ExceedsMaxInvestment { remaining });
+    }
+    location.add_investment(token, maturity_days, amount);
+
+    // the reserve must cover the worst-case payout, including this investment
+    let mut reserve = Reserve::load(deps.storage, token)?;
+    reserve.invested += amount;
+    if !reserve.is_solvent(&config) {
+        return Err(ContractError::InsufficientReserve(denom_name(token)));
+    }
 
-    let last_index = location.cur_index.ok_or(ContractError::NoDataPresent)?;
-    if last_index.time < env.block.time.seconds() - config.measurement_window * 86400 {
+    let baseline = location.cur_index.ok_or(ContractError::NoDataPresent)?;
+    if baseline.time < env.block.time.seconds() - config.measurement_window * 86400 {
         return Err(ContractError::DataTooOld {
             days: config.measurement_window,
         });
     }
 
-    let invest = Investment {
-        amount: coin.amount,
-        baseline_index: last_index.value,
-        invested_time: invested,
-        maturity_time: maturity_date,
+    Ok(CheckedInvestment {
+        hex,
+        maturity_days,
+        maturity_time,
+        baseline,
+        location,
+        reserve,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    hexes: Option<Vec<String>>,
+    ids: Option<Vec<u64>>,
+    limit: Option<u32>,
+    recipient: Option<String>,
+    msg: Option<Binary>,
+) -> Result<Response, ContractError> {
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender.clone(),
     };
-    INVESTMENTS.update::<_, StdError>(deps.storage, (&sender, &hex), |invs| {
-        let mut invs = invs.unwrap_or_default();
-        invs.push(invest);
-        Ok(invs)
-    })?;
+    let Selection {
+        settle: to_settle,
+        deferred,
+    } = select_withdrawals(deps.as_ref(), &env, &info.sender, hexes, ids, limit)?;
 
-    let evt = Event::new("invest")
-        .add_attribute("index", hex)
-        .add_attribute("amount", coin.amount.to_string())
-        .add_attribute("investor", sender);
-    Ok(Response::new().add_event(evt))
+    // native tokens cannot be delivered with a hook, fail before updating state
+    if msg.is_some() {
+        for (invest, _) in to_settle.iter() {
+            if let Denom::Native(denom) = &invest.token {
+                return Err(ContractError::CannotSendNative(denom.clone()));
+            }
+        }
+    }
+
+    let settled = settle_withdrawals(deps.as_ref(), &to_settle)?;
+    for (invest, _) in to_settle.iter() {
+        investments().remove(deps.storage, invest.id.into())?;
+    }
+    count_investments(deps.storage, 0, to_settle.len() as u64)?;
+    for (hex, loc) in settled.locations.iter() {
+        LOCATIONS.save(deps.storage, hex, loc)?;
+    }
+    for reserve in settled.reserves.values() {
+        reserve.save(deps.storage)?;
+    }
+
+    let mut events: Vec<_> = to_settle
+        .iter()
+        .map(|(invest, reward)| withdraw_event(invest, *reward))
+        .chain(deferred.iter().map(|(invest, reward)| {
+            Event::new("withdraw-deferred")
+                .add_attribute("investment_id", invest.id.to_string())
+                .add_attribute("invested", invest.amount)
+                .add_attribute("payout", *reward)
+                .add_attribute("hex", &invest.hex)
+                .add_attribute("investor", &invest.investor)
+        }))
+        .collect();
+    let mut msgs = Vec::with_capacity(settled.totals.len());
+    for (token, amount) in settled.totals.into_iter().filter(|(_, a)| !a.is_zero()) {
+        msgs.push(payout(&token, &recipient, amount, msg.clone())?);
+        let evt = Event::new("withdraw-total")
+            .add_attribute("amount", amount.to_string())
+            .add_attribute("token", denom_name(&token))
+            .add_attribute("investor", &info.sender)
+            .add_attribute("recipient", &recipient);
+        events.push(evt);
+    }
+    // even without anything to pay out, the investments are gone
+    Ok(Response::new().add_events(events).add_messages(msgs))
+}
+
+/// Investments to redeem, along with their payout
+struct Selection {
+    settle: Vec<(Investment, Uint128)>,
+    // mature ones whose gain the reserve cannot pay yet, left open
+    deferred: Vec<(Investment, Uint128)>,
 }
 
-pub fn withdraw(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+/// All investments of `investor` to redeem, along with their payout.
+/// Shared by `withdraw` and the `SimulateWithdraw` query
+fn select_withdrawals(
+    deps: Deps,
+    env: &Env,
+    investor: &Addr,
+    hexes: Option<Vec<String>>,
+    ids: Option<Vec<u64>>,
+    limit: Option<u32>,
+) -> Result<Selection, ContractError> {
+    PauseFlags::load(deps.storage)?.assert_withdrawals()?;
     let cfg = CONFIG.load(deps.storage)?;
+    let hexes = hexes
+        .map(|hexes| {
+            hexes
+                .into_iter()
+                .map(validate_r3)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let in_hexes = |invest: &Investment| match &hexes {
+        Some(hexes) => hexes.contains(&invest.hex),
+        None => true,
+    };
+    let limit = limit.map(|l| l as usize).unwrap_or(usize::MAX);
 
-    let investments: StdResult<Vec<_>> = INVESTMENTS
-        .prefix_de(&info.sender)
-        .range(deps.storage, None, None, Order::Ascending)
-        .collect();
+    match ids {
+        Some(ids) => {
+            // explicitly requested ones must all be redeemable, and each only paid once
+            let mut seen = BTreeSet::new();
+            if let Some(&id) = ids.iter().find(|&&id| !seen.insert(id)) {
+                return Err(ContractError::DuplicateInvestment(id));
+            }
+            let settle = ids
+                .into_iter()
+                .take(limit)
+                .map(|id| {
+                    let invest = investments()
+                        .may_load(deps.storage, id.into())?
+                        .filter(|invest| &invest.investor == investor && in_hexes(invest))
+                        .ok_or(ContractError::InvestmentNotFound(id))?;
+                    let settlement =
+                        measurement_after(deps.storage, &invest.hex, invest.maturity_time)?;
+                    let reward = invest
+                        .reward(env, settlement, &cfg)?
+                        .ok_or(ContractError::CannotWithdraw(id))?;
+                    Ok((invest, reward))
+                })
+                .collect::<Result<_, ContractError>>()?;
+            Ok(Selection {
+                settle,
+                deferred: vec![],
+            })
+        }
+        None => {
+            // otherwise, take the first ones that can be redeemed
+            let redeemable = investments()
+                .idx
+                .investor
+                .prefix(investor.clone())
+                .range(deps.storage, None, None, Order::Ascending)
+                .filter_map(|res| {
+                    let invest = match res {
+                        Ok((_, invest)) => invest,
+                        Err(e) => return Some(Err(e.into())),
+                    };
+                    if !invest.is_mature(env) || !in_hexes(&invest) {
+                        return None;
+                    }
+                    let reward = measurement_after(deps.storage, &invest.hex, invest.maturity_time)
+                        .map_err(ContractError::from)
+                        .and_then(|settlement| invest.reward(env, settlement, &cfg));
+                    match reward {
+                        Ok(reward) => reward.map(|reward| Ok((invest, reward))),
+                        Err(e) => Some(Err(e)),
+                    }
+                });
+            fundable(deps.storage, redeemable, limit)
+        }
+    }
+}
+
+/// Takes up to `limit` investments whose gains the reserves can pay, counting in the losses
+/// of those taken. The others are deferred, rather than failing the whole withdrawal
+fn fundable(
+    storage: &dyn Storage,
+    redeemable: impl Iterator<Item = Result<(Investment, Uint128), ContractError>>,
+    limit: usize,
+) -> Result<Selection, ContractError> {
+    // what the reserve of each token can still pay out
+    let mut available = BTreeMap::<String, Uint128>::new();
+    let mut take = |invest: &Investment, reward: Uint128| -> StdResult<bool> {
+        let name = denom_name(&invest.token);
+        if !available.contains_key(&name) {
+            let reserve = Reserve::load(storage, &invest.token)?;
+            available.insert(name.clone(), reserve.reserve);
+        }
+        let left = available.get_mut(&name).unwrap();
+        if reward <= invest.amount {
+            *left += invest.amount - reward;
+        } else if reward - invest.amount <= *left {
+            *left -= reward - invest.amount;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    };
+
+    let mut settle = vec![];
+    let mut deferred = vec![];
+    for res in redeemable {
+        if settle.len() >= limit {
+            break;
+        }
+        let (invest, reward) = res?;
+        match take(&invest, reward)? {
+            true => settle.push((invest, reward)),
+            false => deferred.push((invest, reward)),
+        }
+    }
+    // losses found later may still cover the gains deferred before them
+    let mut still_deferred = vec![];
+    for (invest, reward) in deferred.into_iter() {
+        if settle.len() < limit && take(&invest, reward)? {
+            settle.push((invest, reward));
+        } else {
+            still_deferred.push((invest, reward));
+        }
+    }
+    settle.sort_by_key(|(invest, _)| invest.id);
+    Ok(Selection {
+        settle,
+        deferred: still_deferred,
+    })
+}
 
-    let mut to_withdraw = Uint128::zero();
-    let mut events = Vec::<Event>::new();
+/// State after redeeming some investments, not saved yet
+struct Settlement {
+    // locations touched by the redeemed investments
+    locations: BTreeMap<String, Location>,
+    // as well as the reserve ledgers of the tokens paid out
+    reserves: BTreeMap<String, Reserve>,
+    // how much to pay out in each token
+    totals: Vec<(Denom, Uint128)>,
+}
 
-    for (hex, invests) in investments?.into_iter() {
-        let mut loc = LOCATIONS.load(deps.storage, &hex)?;
+fn settle_withdrawals(
+    deps: Deps,
+    to_settle: &[(Investment, Uint128)],
+) -> Result<Settlement, ContractError> {
+    // amount invested and paid out in each token
+    let mut tally = Vec::<(Denom, Uint128, Uint128)>::new();
+    let mut locations = BTreeMap::<String, Location>::new();
 
-        // this filters out to leave non-mature investments
-        // returns a tally from all mature investments of original amounts and rewards to pay out
-        let init: Result<_, ContractError> = Ok((
-            Vec::with_capacity(invests.len()),
-            Uint128::zero(),
-            Uint128::zero(),
-            0u64,
-        ));
-        let (invests, reward, orig, count) = invests.into_iter().fold(init, |acc, invest| {
-            let (mut v, total, orig, count) = acc?;
-            match invest.reward(&env, &loc, &cfg) {
-                Some(reward) => {
-                    events.push(withdraw_event(&hex, &info.sender, &invest, reward));
-                    Ok((v, total + reward, orig + invest.amount, count + 1))
-                }
-                None => {
-                    v.push(invest);
-                    Ok((v, total, orig, count))
-                }
+    for (invest, reward) in to_settle.iter() {
+        let loc = match locations.entry(invest.hex.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(LOCATIONS.load(deps.storage, &invest.hex)?),
+        };
+        // update location state with the redeemed investment
+        loc.finish_investment(&invest.token, invest.maturity_days(), invest.amount, 1)?;
+        match tally.iter_mut().find(|(t, _, _)| t == &invest.token) {
+            Some((_, amount, total)) => {
+                *amount += invest.amount;
+                *total += *reward;
             }
-        })?;
-        // update location state with the redeemed investments
-        loc.finish_investment(orig, count)?;
-        // and tally up how much to pay out
-        to_withdraw += reward;
+            None => tally.push((invest.token.clone(), invest.amount, *reward)),
+        }
+    }
+
+    // gains are paid from the reserve, losses added to it. all at once,
+    // so the losses in a batch cover its gains whatever their order
+    let mut reserves = BTreeMap::<String, Reserve>::new();
+    let mut totals = Vec::with_capacity(tally.len());
+    for (token, amount, total) in tally.into_iter() {
+        let mut reserve = Reserve::load(deps.storage, &token)?;
+        reserve.settle(amount, total)?;
+        reserves.insert(denom_name(&token), reserve);
+        totals.push((token, total));
+    }
+    Ok(Settlement {
+        locations,
+        reserves,
+        totals,
+    })
+}
+
+/// Creates the message to send `amount` of the given token to `recipient`.
+/// If `msg` is set, cw20 tokens are sent to the recipient contract with this hook.
+pub fn payout(
+    token: &Denom,
+    recipient: &Addr,
+    amount: Uint128,
+    msg: Option<Binary>,
+) -> Result<CosmosMsg, ContractError> {
+    let msg = match (token, msg) {
+        (Denom::Native(denom), None) => BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: coins(amount.u128(), denom),
+        }
+        .into(),
+        (Denom::Native(denom), Some(_)) => {
+            return Err(ContractError::CannotSendNative(denom.clone()))
+        }
+        (Denom::Cw20(addr), None) => Cw20Contract(addr.clone()).call(Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount,
+        })?,
+        (Denom::Cw20(addr), Some(msg)) => {
+            Cw20Contract(addr.clone()).call(Cw20ExecuteMsg::Send {
+                contract: recipient.to_string(),
+                amount,
+                msg,
+            })?
+        }
+    };
+    Ok(msg)
+}
+
+pub fn exit_early(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    PauseFlags::load(deps.storage)?.assert_withdrawals()?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let terms = cfg.early_exit.ok_or(ContractError::EarlyExitDisabled)?;
+    let invest = investments()
+        .may_load(deps.storage, id.into())?
+        .filter(|invest| invest.investor == info.sender)
+        .ok_or(ContractError::InvestmentNotFound(id))?;
+    if invest.is_mature(&env) {
+        return Err(ContractError::AlreadyMature(id));
+    }
+
+    let penalty = terms.penalty_for(&invest, &env)?;
+    let payout_amount = invest.amount - penalty;
 
-        LOCATIONS.save(deps.storage, &hex, &loc)?;
-        INVESTMENTS.save(deps.storage, (&info.sender, &hex), &invests)?;
+    let mut loc = LOCATIONS.load(deps.storage, &invest.hex)?;
+    loc.finish_investment(&invest.token, invest.maturity_days(), invest.amount, 1)?;
+    LOCATIONS.save(deps.storage, &invest.hex, &loc)?;
+    // the penalty stays in the reserve, unless it goes to the fee collector
+    let mut reserve = Reserve::load(deps.storage, &invest.token)?;
+    match terms.fee_collector {
+        Some(_) => reserve.settle(invest.amount, invest.amount)?,
+        None => reserve.settle(invest.amount, payout_amount)?,
     }
+    reserve.save(deps.storage)?;
+    investments().remove(deps.storage, id.into())?;
+    count_investments(deps.storage, 0, 1)?;
 
-    if to_withdraw.is_zero() {
-        return Ok(Response::new());
+    let mut msgs = vec![];
+    if !payout_amount.is_zero() {
+        msgs.push(payout(&invest.token, &info.sender, payout_amount, None)?);
+    }
+    if let Some(collector) = &terms.fee_collector {
+        if !penalty.is_zero() {
+            msgs.push(payout(&invest.token, collector, penalty, None)?);
+        }
     }
 
-    let msg = Cw20Contract(cfg.token).call(Cw20ExecuteMsg::Transfer {
-        recipient: info.sender.to_string(),
-        amount: to_withdraw,
-    })?;
-    let evt = Event::new("withdraw-total")
-        .add_attribute("amount", to_withdraw.to_string())
-        .add_attribute("investor", info.sender);
-    events.push(evt);
-    Ok(Response::new().add_events(events).add_message(msg))
+    let evt = Event::new("exit-early")
+        .add_attribute("investment_id", id.to_string())
+        .add_attribute("invested", invest.amount)
+        .add_attribute("penalty", penalty)
+        .add_attribute("payout", payout_amount)
+        .add_attribute("hex", &invest.hex)
+        .add_attribute("investor", &invest.investor);
+    Ok(Response::new().add_event(evt).add_messages(msgs))
 }
 
-pub fn withdraw_event(hex: &str, sender: &Addr, invest: &Investment, reward: Uint128) -> Event {
+pub fn withdraw_event(invest: &Investment, reward: Uint128) -> Event {
     Event::new("withdraw")
+        .add_attribute("investment_id", invest.id.to_string())
         .add_attribute("invested", invest.amount)
         .add_attribute("payout", reward)
-        .add_attribute("hex", hex)
-        .add_attribute("investor", sender)
+        .add_attribute("hex", &invest.hex)
+        .add_attribute("investor", &invest.investor)
 }
 
 pub fn store_oracle(
-    mut deps: DepsMut,
+    deps: DepsMut,
     env: Env,
     info: MessageInfo,
     values: Vec<OracleValues>,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    ensure_eq!(config.oracle, info.sender, ContractError::Unauthorized {});
+    if !config.is_oracle(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    store_values(deps, env, &config, &info.sender, values)
+}
+
+pub fn store_signed_oracle(
+    deps: DepsMut,
+    env: Env,
+    oracle: String,
+    nonce: u64,
+    values: Vec<OracleValues>,
+    signature: Binary,
+) -> Result<Response, ContractError> {
+    let oracle = deps.api.addr_validate(&oracle)?;
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_oracle(&oracle) {
+        return Err(ContractError::NotOracle(oracle.into()));
+    }
+    let key = ORACLE_KEYS
+        .may_load(deps.storage, &oracle)?
+        .ok_or_else(|| ContractError::NoOracleKey(oracle.to_string()))?;
+    let last_nonce = ORACLE_NONCES
+        .may_load(deps.storage, &oracle)?
+        .unwrap_or_default();
+    if nonce <= last_nonce {
+        return Err(ContractError::StaleNonce(last_nonce));
+    }
+    let payload = signed_payload(&env.contract.address, &oracle, nonce, &values)?;
+    key.verify(deps.api, &payload, &signature)?;
+    ORACLE_NONCES.save(deps.storage, &oracle, &nonce)?;
+    store_values(deps, env, &config, &oracle, values)
+}
+
+/// Stores the values submitted by one oracle, reporting the ones that failed in the event
+fn store_values(
+    mut deps: DepsMut,
+    env: Env,
+    config: &Config,
+    oracle: &Addr,
+    values: Vec<OracleValues>,
+) -> Result<Response, ContractError> {
+    PauseFlags::load(deps.storage)?.assert_oracle()?;
 
     let mut invalid = Vec::<ContractError>::new();
 
     let mut count = 0;
+    let mut pending = 0;
     for val in values.into_iter() {
-        if let Err(e) = process_oracle(deps.branch(), &env, val) {
-            invalid.push(e);
-        } else {
-            count += 1;
+        match process_oracle(deps.branch(), &env, config, oracle, val) {
+            Err(e) => invalid.push(e),
+            Ok(committed) => {
+                count += 1;
+                if !committed {
+                    pending += 1;
+                }
+            }
         }
     }
 
     let evt = Event::new("oracle")
         .add_attribute("succeeded", count.to_string())
-        .add_attributes(invalid.into_iter().map(|e| ("failed", e.to_string())));
+        .add_attributes(invalid.into_iter().map(|e| ("failed", e.to_string())))
+        .add_attribute("pending", pending.to_string());
     Ok(Response::new().add_event(evt))
 }
 
-fn process_oracle(deps: DepsMut, env: &Env, val: OracleValues) -> Result<(), ContractError> {
+/// Stores the value once the quorum of oracles submitted one for this hex and time,
+/// returning false while it is still waiting for more oracles
+fn process_oracle(
+    deps: DepsMut,
+    env: &Env,
+    cfg: &Config,
+    oracle: &Addr,
+    val: OracleValues,
+) -> Result<bool, ContractError> {
     let hex = validate_r3(val.index)?;
     let mut loc = LOCATIONS
         .load(deps.storage, &hex)
@@ -230,121 +823,804 @@ fn process_oracle(deps: DepsMut, env: &Env, val: OracleValues) -> Result<(), Con
     // if oracle is not newer, abort earlier
     if let Some(cur) = loc.cur_index {
         if cur.time >= val.time {
-            return Ok(());
+            return Ok(true);
         }
+        cfg.oracle_guards.check(&cur, val.value, val.time)?;
+    }
+    if let Some(range) = &loc.value_range {
+        range.check(val.value)?;
     }
-    // update stored value
+
+    // buffer until enough oracles agree, a resubmission replaces the earlier value.
+    // values of oracles handed over since no longer count
+    let value = if cfg.quorum > 1 {
+        let mut submissions = SUBMISSIONS
+            .may_load(deps.storage, (&hex, val.time.into()))?
+            .unwrap_or_default();
+        submissions.retain(|s| &s.oracle != oracle && cfg.is_oracle(&s.oracle));
+        submissions.push(Submission {
+            oracle: oracle.clone(),
+            value: val.value,
+        });
+        if submissions.len() < cfg.quorum as usize {
+            SUBMISSIONS.save(deps.storage, (&hex, val.time.into()), &submissions)?;
+            return Ok(false);
+        }
+        // this one and all older ones can no longer be stored
+        let stale: StdResult<Vec<u64>> = SUBMISSIONS
+            .prefix_de(&hex)
+            .keys_de(
+                deps.storage,
+                None,
+                Some(Bound::inclusive_int(val.time)),
+                Order::Ascending,
+            )
+            .collect();
+        for time in stale?.into_iter() {
+            SUBMISSIONS.remove(deps.storage, (&hex, time.into()));
+        }
+        median(submissions.into_iter().map(|s| s.value).collect()).unwrap_or(val.value)
+    } else {
+        val.value
+    };
+
+    // update stored value and keep it in the history
     loc.cur_index = Some(Measurement {
-        value: val.value,
+        value,
         time: val.time,
     });
     LOCATIONS.save(deps.storage, &hex, &loc)?;
-    Ok(())
+    MEASUREMENTS.save(deps.storage, (&hex, val.time.into()), &value)?;
+    Ok(true)
 }
 
-pub fn update_oracle(
+pub fn propose_oracle(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
+    info: MessageInfo,
+    oracle: String,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let oracle = deps.api.addr_validate(&oracle)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    if !cfg.is_oracle(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if cfg.is_oracle(&oracle) {
+        return Err(ContractError::DuplicateOracle(oracle.into()));
+    }
+    let expires =
+        expires.unwrap_or_else(|| Expiration::AtTime(env.block.time.plus_seconds(HANDOVER_TIME)));
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired);
+    }
+    let pending = PendingOracle {
+        oracle: oracle.clone(),
+        expires,
+    };
+    PENDING_ORACLES.save(deps.storage, &info.sender, &pending)?;
+
+    let evt = Event::new("propose-oracle")
+        .add_attribute("oracle", info.sender)
+        .add_attribute("proposed", oracle)
+        .add_attribute("expires", expires.to_string());
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn accept_oracle(
+    deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    raw_oracle: String,
 ) -> Result<Response, ContractError> {
-    let oracle = deps.api.addr_validate(&raw_oracle)?;
+    // few oracles, so few proposals to look through
+    let proposals: StdResult<Vec<_>> = PENDING_ORACLES
+        .range_de(deps.storage, None, None, Order::Ascending)
+        .collect();
+    let (previous, pending) = proposals?
+        .into_iter()
+        .find(|(_, pending)| pending.oracle == info.sender)
+        .ok_or(ContractError::NoPendingOracle)?;
+    if pending.expires.is_expired(&env.block) {
+        return Err(ContractError::Expired);
+    }
 
     let mut cfg = CONFIG.load(deps.storage)?;
-    ensure_eq!(cfg.oracle, info.sender, ContractError::Unauthorized {});
-    cfg.oracle = oracle;
+    // another handover may have made it an oracle in the meantime
+    if cfg.is_oracle(&pending.oracle) {
+        return Err(ContractError::DuplicateOracle(pending.oracle.into()));
+    }
+    if cfg.oracle == previous {
+        cfg.oracle = pending.oracle;
+    } else {
+        cfg.oracles.retain(|o| o != &previous);
+        cfg.oracles.push(pending.oracle);
+    }
     CONFIG.save(deps.storage, &cfg)?;
+    PENDING_ORACLES.remove(deps.storage, &previous);
+    ORACLE_KEYS.remove(deps.storage, &previous);
+    // its buffered values are ignored from now on, rather than going through the buffers of all hexes
 
-    let evt = Event::new("update-oracle").add_attribute("oracle", raw_oracle);
+    let evt = Event::new("accept-oracle")
+        .add_attribute("oracle", info.sender)
+        .add_attribute("previous", previous);
     Ok(Response::new().add_event(evt))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
-    match msg {
-        QueryMsg::Config {} => Ok(to_binary(&query_config(deps)?)?),
-        QueryMsg::Info { hex } => Ok(to_binary(&query_info(deps, hex)?)?),
-        QueryMsg::ListInvestments { investor, hex } => {
-            Ok(to_binary(&list_investments(deps, env, investor, hex)?)?)
+pub fn cancel_oracle(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending = PENDING_ORACLES
+        .may_load(deps.storage, &info.sender)?
+        .ok_or(ContractError::NoPendingOracle)?;
+    PENDING_ORACLES.remove(deps.storage, &info.sender);
+
+    let evt = Event::new("cancel-oracle")
+        .add_attribute("oracle", info.sender)
+        .add_attribute("proposed", pending.oracle);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn set_oracle_key(
+    deps: DepsMut,
+    info: MessageInfo,
+    oracle: String,
+    key: Option<OracleKey>,
+) -> Result<Response, ContractError> {
+    let oracle = deps.api.addr_validate(&oracle)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    // only the oracle may set its key, otherwise the admin could sign as every oracle.
+    // the admin may remove one though, if it got compromised
+    if oracle != info.sender {
+        if key.is_some() {
+            return Err(ContractError::Unauthorized {});
+        }
+        assert_admin(&cfg, &info.sender)?;
+    }
+
+    let mut evt = Event::new("oracle-key").add_attribute("oracle", &oracle);
+    match key {
+        Some(key) => {
+            register_oracle_key(deps.storage, &cfg, &oracle, &key)?;
+            evt = evt.add_attribute("action", "register");
+        }
+        None => {
+            ORACLE_KEYS.remove(deps.storage, &oracle);
+            evt = evt.add_attribute("action", "remove");
         }
     }
+    Ok(Response::new().add_event(evt))
 }
 
-fn query_config(deps: Deps) -> Result<ConfigResponse, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-    Ok(config)
+fn register_oracle_key(
+    storage: &mut dyn Storage,
+    cfg: &Config,
+    oracle: &Addr,
+    key: &OracleKey,
+) -> Result<(), ContractError> {
+    if !cfg.is_oracle(oracle) {
+        return Err(ContractError::NotOracle(oracle.into()));
+    }
+    key.validate()?;
+    // one key per oracle, otherwise a single signer could make up the quorum on its own.
+    // few oracles, so few keys to look through
+    for res in ORACLE_KEYS.range_de(storage, None, None, Order::Ascending) {
+        let (owner, registered) = res?;
+        if &registered == key && &owner != oracle {
+            return Err(ContractError::DuplicateOracleKey(owner.into()));
+        }
+    }
+    ORACLE_KEYS.save(storage, oracle, key)?;
+    Ok(())
 }
 
-fn query_info(deps: Deps, hex: String) -> Result<InfoResponse, ContractError> {
-    let hex = validate_r3(hex)?;
-    let info = LOCATIONS.load(deps.storage, &hex)?;
-    Ok(InfoResponse {
-        cur_index: info.cur_index,
-        total_invested: info.total_invested,
-        current_invested: info.current_invested,
-        total_investments: info.total_investments,
-        current_investments: info.current_investments,
-    })
+fn assert_admin(cfg: &Config, sender: &Addr) -> Result<(), ContractError> {
+    match &cfg.admin {
+        Some(admin) if admin == sender => Ok(()),
+        _ => Err(ContractError::Unauthorized {}),
+    }
 }
 
-fn list_investments(
-    deps: Deps,
-    env: Env,
-    investor: String,
-    hex: Option<String>,
-) -> Result<ListInvestmentsResponse, ContractError> {
-    let hex = hex.map(validate_r3).transpose()?;
-    let investor = deps.api.addr_validate(&investor)?;
+pub fn set_pause(
+    deps: DepsMut,
+    info: MessageInfo,
+    investments: Option<bool>,
+    oracle: Option<bool>,
+    withdrawals: Option<bool>,
+) -> Result<Response, ContractError> {
     let cfg = CONFIG.load(deps.storage)?;
+    if cfg.pauser.as_ref() != Some(&info.sender) {
+        assert_admin(&cfg, &info.sender)?;
+    }
 
-    let investments = if let Some(hex) = hex {
-        let loc = LOCATIONS.load(deps.storage, &hex)?;
-        INVESTMENTS
-            .load(deps.storage, (&investor, &hex))?
-            .into_iter()
-            .map(|inv| InvestmentResponse::new(inv, &hex, &cfg, &loc, &env))
-            .collect()
-    } else {
-        // all for this investor
-        let nested: StdResult<Vec<Vec<_>>> = INVESTMENTS
-            .prefix_de(&investor)
-            .range(deps.storage, None, None, Order::Ascending)
-            .map(|res| {
-                let (hex, invs) = res?;
-                let loc = LOCATIONS.load(deps.storage, &hex)?;
-                Ok(invs
-                    .into_iter()
-                    .map(|i| InvestmentResponse::new(i, &hex, &cfg, &loc, &env))
-                    .collect())
-            })
-            .collect();
-        nested?.into_iter().flatten().collect()
-    };
-
-    Ok(ListInvestmentsResponse { investments })
-}
+    let mut flags = PauseFlags::load(deps.storage)?;
+    flags.investments = investments.unwrap_or(flags.investments);
+    flags.oracle = oracle.unwrap_or(flags.oracle);
+    flags.withdrawals = withdrawals.unwrap_or(flags.withdrawals);
+    PAUSED.save(deps.storage, &flags)?;
 
-// this is useful so we can patch on top.
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    let version = get_contract_version(deps.storage)?;
-    ensure_eq!(
-        version.contract,
-        CONTRACT_NAME,
-        ContractError::InvalidMigration
-    );
-    // FIXME: better compare...
-    if version.version.as_str() > CONTRACT_VERSION {
-        return Err(ContractError::InvalidMigration);
-    }
-    Ok(Response::new())
+    let evt = Event::new("pause")
+        .add_attribute("investments", flags.investments.to_string())
+        .add_attribute("oracle", flags.oracle.to_string())
+        .add_attribute("withdrawals", flags.withdrawals.to_string())
+        .add_attribute("sender", info.sender);
+    Ok(Response::new().add_event(evt))
 }
 
-#[cfg(test)]
-mod tests {
+pub fn add_locations(
+    deps: DepsMut,
+    info: MessageInfo,
+    locations: Vec<String>,
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    assert_admin(&cfg, &info.sender)?;
+
+    let mut events = Vec::with_capacity(locations.len());
+    for index in locations.into_iter() {
+        let hex = validate_r3(index)?;
+        cfg.resolutions.check(&hex)?;
+        if LOCATIONS.has(deps.storage, &hex) {
+            return Err(ContractError::LocationExists(hex));
+        }
+        LOCATIONS.save(deps.storage, &hex, &Location::new())?;
+        events.push(Event::new("add-location").add_attribute("hex", hex));
+    }
+    Ok(Response::new().add_events(events))
+}
+
+pub fn retire_location(
+    deps: DepsMut,
+    info: MessageInfo,
+    hex: String,
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    assert_admin(&cfg, &info.sender)?;
+
+    let hex = validate_r3(hex)?;
+    let mut loc = LOCATIONS
+        .may_load(deps.storage, &hex)?
+        .ok_or_else(|| ContractError::UnregisteredLocation(hex.clone()))?;
+    if loc.retired {
+        return Err(ContractError::LocationRetired(hex));
+    }
+    loc.retired = true;
+    LOCATIONS.save(deps.storage, &hex, &loc)?;
+
+    let evt = Event::new("retire-location").add_attribute("hex", hex);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn reopen_location(
+    deps: DepsMut,
+    info: MessageInfo,
+    hex: String,
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    assert_admin(&cfg, &info.sender)?;
+
+    let hex = validate_r3(hex)?;
+    let mut loc = LOCATIONS
+        .may_load(deps.storage, &hex)?
+        .ok_or_else(|| ContractError::UnregisteredLocation(hex.clone()))?;
+    if !loc.retired {
+        return Err(ContractError::LocationNotRetired(hex));
+    }
+    loc.retired = false;
+    LOCATIONS.save(deps.storage, &hex, &loc)?;
+
+    let evt = Event::new("reopen-location").add_attribute("hex", hex);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn set_oracle_guards(
+    deps: DepsMut,
+    info: MessageInfo,
+    guards: OracleGuards,
+) -> Result<Response, ContractError> {
+    let mut cfg = CONFIG.load(deps.storage)?;
+    assert_admin(&cfg, &info.sender)?;
+    cfg.oracle_guards = guards;
+    CONFIG.save(deps.storage, &cfg)?;
+
+    let guards = &cfg.oracle_guards;
+    let evt = Event::new("oracle-guards")
+        .add_attribute(
+            "max_deviation",
+            guards
+                .max_deviation
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+        )
+        .add_attribute(
+            "min_interval",
+            guards
+                .min_interval
+                .map(|i| i.to_string())
+                .unwrap_or_default(),
+        );
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn set_value_range(
+    deps: DepsMut,
+    info: MessageInfo,
+    hex: String,
+    range: Option<ValueRange>,
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    assert_admin(&cfg, &info.sender)?;
+
+    let hex = validate_r3(hex)?;
+    let mut loc = LOCATIONS
+        .may_load(deps.storage, &hex)?
+        .ok_or_else(|| ContractError::UnregisteredLocation(hex.clone()))?;
+    if let Some(range) = &range {
+        range.validate()?;
+    }
+    let mut evt = Event::new("value-range").add_attribute("hex", &hex);
+    if let Some(range) = &range {
+        evt = evt
+            .add_attribute("min", range.min.to_string())
+            .add_attribute("max", range.max.to_string());
+    }
+    loc.value_range = range;
+    LOCATIONS.save(deps.storage, &hex, &loc)?;
+    Ok(Response::new().add_event(evt))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
+    match msg {
+        QueryMsg::Config {} => Ok(to_binary(&query_config(deps)?)?),
+        QueryMsg::Info { hex } => Ok(to_binary(&query_info(deps, hex)?)?),
+        QueryMsg::Capacity { hex } => Ok(to_binary(&query_capacity(deps, hex)?)?),
+        QueryMsg::Portfolio {
+            investor,
+            start_after,
+            limit,
+        } => Ok(to_binary(&query_portfolio(
+            deps,
+            env,
+            investor,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::SimulateInvest {
+            hex,
+            amount,
+            token,
+            maturity_days,
+        } => Ok(to_binary(&simulate_invest(
+            deps,
+            env,
+            hex,
+            amount,
+            token,
+            maturity_days,
+        )?)?),
+        QueryMsg::SimulateWithdraw { investor } => {
+            Ok(to_binary(&simulate_withdraw(deps, env, investor)?)?)
+        }
+        QueryMsg::ListInvestments {
+            investor,
+            hex,
+            start_after,
+            limit,
+        } => Ok(to_binary(&list_investments(
+            deps,
+            env,
+            investor,
+            hex,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::Investment { id } => Ok(to_binary(&query_investment(deps, env, id)?)?),
+        QueryMsg::ListLocations { start_after, limit } => {
+            Ok(to_binary(&list_locations(deps, start_after, limit)?)?)
+        }
+        QueryMsg::Solvency {} => Ok(to_binary(&query_solvency(deps)?)?),
+        QueryMsg::PauseStatus {} => Ok(to_binary(&PauseFlags::load(deps.storage)?)?),
+        QueryMsg::OracleKey { oracle } => Ok(to_binary(&query_oracle_key(deps, oracle)?)?),
+        QueryMsg::PendingOracle { oracle } => Ok(to_binary(&query_pending_oracle(deps, oracle)?)?),
+        QueryMsg::OwnerOf {
+            token_id,
+            include_expired,
+        } => Ok(to_binary(&nft::query_owner_of(
+            deps,
+            env,
+            token_id,
+            include_expired,
+        )?)?),
+        QueryMsg::AllOperators {
+            owner,
+            include_expired,
+            start_after,
+            limit,
+        } => Ok(to_binary(&nft::query_all_operators(
+            deps,
+            env,
+            owner,
+            include_expired,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::NumTokens {} => Ok(to_binary(&nft::query_num_tokens(deps)?)?),
+        QueryMsg::ContractInfo {} => Ok(to_binary(&nft::query_contract_info())?),
+        QueryMsg::NftInfo { token_id } => Ok(to_binary(&nft::query_nft_info(deps, token_id)?)?),
+        QueryMsg::AllNftInfo {
+            token_id,
+            include_expired,
+        } => Ok(to_binary(&nft::query_all_nft_info(
+            deps,
+            env,
+            token_id,
+            include_expired,
+        )?)?),
+        QueryMsg::Tokens {
+            owner,
+            start_after,
+            limit,
+        } => Ok(to_binary(&nft::query_tokens(
+            deps,
+            owner,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::AllTokens { start_after, limit } => Ok(to_binary(&nft::query_all_tokens(
+            deps,
+            start_after,
+            limit,
+        )?)?),
+    }
+}
+
+fn query_solvency(deps: Deps) -> Result<SolvencyResponse, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let tokens = cfg
+        .tokens
+        .iter()
+        .map(|token| {
+            Ok(TokenSolvency::new(
+                Reserve::load(deps.storage, token)?,
+                &cfg,
+            ))
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    let solvent = tokens.iter().all(|t| t.solvent);
+    Ok(SolvencyResponse { solvent, tokens })
+}
+
+fn query_pending_oracle(
+    deps: Deps,
+    oracle: String,
+) -> Result<PendingOracleResponse, ContractError> {
+    let oracle = deps.api.addr_validate(&oracle)?;
+    let pending = PENDING_ORACLES.may_load(deps.storage, &oracle)?;
+    Ok(PendingOracleResponse { pending })
+}
+
+fn query_oracle_key(deps: Deps, oracle: String) -> Result<OracleKeyResponse, ContractError> {
+    let oracle = deps.api.addr_validate(&oracle)?;
+    let key = ORACLE_KEYS.may_load(deps.storage, &oracle)?;
+    let last_nonce = ORACLE_NONCES
+        .may_load(deps.storage, &oracle)?
+        .unwrap_or_default();
+    Ok(OracleKeyResponse { key, last_nonce })
+}
+
+fn query_config(deps: Deps) -> Result<ConfigResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(config)
+}
+
+fn query_info(deps: Deps, hex: String) -> Result<InfoResponse, ContractError> {
+    let hex = validate_r3(hex)?;
+    let info = LOCATIONS.load(deps.storage, &hex)?;
+    Ok(InfoResponse {
+        cur_index: info.cur_index,
+        retired: info.retired,
+        total_invested: info.total_invested,
+        current_invested: info.current_invested,
+        total_investments: info.total_investments,
+        current_investments: info.current_investments,
+        tokens: info.tokens,
+        tiers: info.tiers,
+        value_range: info.value_range,
+    })
+}
+
+fn query_capacity(deps: Deps, hex: String) -> Result<CapacityResponse, ContractError> {
+    let hex = validate_r3(hex)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let loc = LOCATIONS.load(deps.storage, &hex)?;
+    Ok(CapacityResponse {
+        max_investment: cfg.max_investment_hex,
+        current_invested: loc.current_invested,
+        remaining: loc.remaining_capacity(&cfg),
+    })
+}
+
+fn query_investment(
+    deps: Deps,
+    env: Env,
+    id: u64,
+) -> Result<InvestmentInfoResponse, ContractError> {
+    let invest = investments()
+        .may_load(deps.storage, id.into())?
+        .ok_or(ContractError::InvestmentNotFound(id))?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let loc = LOCATIONS.load(deps.storage, &invest.hex)?;
+    let investor = invest.investor.clone();
+    let investment = InvestmentResponse::new(deps.storage, invest, &cfg, &loc, &env)?;
+    Ok(InvestmentInfoResponse {
+        investor,
+        investment,
+    })
+}
+
+fn list_investments(
+    deps: Deps,
+    env: Env,
+    investor: String,
+    hex: Option<String>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<ListInvestmentsResponse, ContractError> {
+    let hex = hex.map(validate_r3).transpose()?;
+    let investor = deps.api.addr_validate(&investor)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive_int);
+
+    let investments: Result<Vec<_>, ContractError> = investments()
+        .idx
+        .investor
+        .prefix(investor)
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|res| match (res, &hex) {
+            (Ok((_, invest)), Some(hex)) => &invest.hex == hex,
+            _ => true,
+        })
+        .take(limit)
+        .map(|res| {
+            let (_, invest) = res?;
+            let loc = LOCATIONS.load(deps.storage, &invest.hex)?;
+            InvestmentResponse::new(deps.storage, invest, &cfg, &loc, &env)
+        })
+        .collect();
+
+    Ok(ListInvestmentsResponse {
+        investments: investments?,
+    })
+}
+
+fn query_portfolio(
+    deps: Deps,
+    env: Env,
+    investor: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<PortfolioResponse, ContractError> {
+    let investor = deps.api.addr_validate(&investor)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as u64;
+    let start = start_after.map(Bound::exclusive_int);
+
+    let mut portfolio = PortfolioResponse::default();
+    let mut locations = BTreeMap::<String, Location>::new();
+    for res in investments()
+        .idx
+        .investor
+        .prefix(investor)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit as usize)
+    {
+        let (_, invest) = res?;
+        let loc = match locations.entry(invest.hex.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(LOCATIONS.load(deps.storage, &invest.hex)?),
+        };
+        let settlement = measurement_after(deps.storage, &invest.hex, invest.maturity_time)?;
+        let claimable = invest.reward(&env, settlement, &cfg)?;
+        let projected = match claimable {
+            Some(reward) => reward,
+            None => invest.would_reward(loc, &cfg)?,
+        };
+
+        portfolio.investments += 1;
+        if invest.is_mature(&env) {
+            portfolio.mature += 1;
+        } else {
+            portfolio.next_maturity = Some(match portfolio.next_maturity {
+                Some(next) => next.min(invest.maturity_time),
+                None => invest.maturity_time,
+            });
+        }
+        let totals = match portfolio
+            .tokens
+            .iter()
+            .position(|t| t.token == invest.token)
+        {
+            Some(idx) => &mut portfolio.tokens[idx],
+            None => {
+                portfolio
+                    .tokens
+                    .push(PortfolioTotals::new(invest.token.clone()));
+                portfolio.tokens.last_mut().unwrap()
+            }
+        };
+        totals.principal += invest.amount;
+        totals.claimable += claimable.unwrap_or_default();
+        totals.projected += projected;
+        if portfolio.investments == limit {
+            portfolio.last_id = Some(invest.id);
+        }
+    }
+    Ok(portfolio)
+}
+
+fn simulate_invest(
+    deps: Deps,
+    env: Env,
+    hex: String,
+    amount: Uint128,
+    token: Option<Denom>,
+    maturity_days: Option<u64>,
+) -> Result<SimulateInvestResponse, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let token = match token {
+        Some(token) => token,
+        None => cfg.tokens[0].clone(),
+    };
+    let checked = check_invest(deps, &env, &token, amount, hex, maturity_days)?;
+    Ok(SimulateInvestResponse {
+        hex: checked.hex,
+        baseline_index: checked.baseline,
+        maturity_days: checked.maturity_days,
+        maturity_date: checked.maturity_time,
+        max_payout: checked_mul(amount, cfg.max_multiplier())?,
+        remaining_capacity: checked.location.remaining_capacity(&cfg),
+    })
+}
+
+fn simulate_withdraw(
+    deps: Deps,
+    env: Env,
+    investor: String,
+) -> Result<SimulateWithdrawResponse, ContractError> {
+    let investor = deps.api.addr_validate(&investor)?;
+    let selection = select_withdrawals(deps, &env, &investor, None, None, None)?;
+    let settled = settle_withdrawals(deps, &selection.settle)?;
+    let simulated = |(invest, payout): (Investment, Uint128)| SimulatedWithdrawal {
+        id: invest.id,
+        hex: invest.hex,
+        token: invest.token,
+        amount: invest.amount,
+        payout,
+    };
+    Ok(SimulateWithdrawResponse {
+        investments: selection.settle.into_iter().map(simulated).collect(),
+        deferred: selection.deferred.into_iter().map(simulated).collect(),
+        totals: settled
+            .totals
+            .into_iter()
+            .map(|(token, amount)| TokenAmount { token, amount })
+            .collect(),
+    })
+}
+
+fn list_locations(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<ListLocationsResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(validate_r3)
+        .transpose()?
+        .map(|hex| Bound::exclusive(hex.as_bytes()));
+
+    let locations: StdResult<Vec<_>> = LOCATIONS
+        .range_de(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|res| {
+            let (hex, loc) = res?;
+            Ok(LocationResponse::new(hex, loc))
+        })
+        .collect();
+
+    Ok(ListLocationsResponse {
+        locations: locations?,
+    })
+}
+
+// this is useful so we can patch on top.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let version = get_contract_version(deps.storage)?;
+    ensure_eq!(
+        version.contract,
+        CONTRACT_NAME,
+        ContractError::InvalidMigration
+    );
+    let from = parse_version(&version.version)?;
+    let to = parse_version(CONTRACT_VERSION)?;
+    if from > to {
+        return Err(ContractError::InvalidMigration);
+    }
+
+    let admin = msg
+        .admin
+        .map(|admin| deps.api.addr_validate(&admin))
+        .transpose()?;
+    let pauser = msg
+        .pauser
+        .map(|pauser| deps.api.addr_validate(&pauser))
+        .transpose()?;
+    if matches!(msg.max_payout, Some(max_payout) if max_payout < Decimal::one()) {
+        return Err(ContractError::InvalidMaxPayout);
+    }
+    if let Some(resolutions) = &msg.resolutions {
+        resolutions.validate()?;
+    }
+
+    // rewrite the stored state for every release since the one deployed
+    let mut migrated = 0;
+    let steps = upgrades_between(&from, &to);
+    for step in steps.iter() {
+        migrated += (step.upgrade)(deps.storage)?;
+    }
+
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if admin.is_some() {
+        cfg.admin = admin;
+    }
+    if pauser.is_some() {
+        cfg.pauser = pauser;
+    }
+    if let Some(max_investment_hex) = msg.max_investment_hex {
+        cfg.max_investment_hex = max_investment_hex;
+    }
+    if let Some(measurement_window) = msg.measurement_window {
+        cfg.measurement_window = measurement_window;
+    }
+    if let Some(max_payout) = msg.max_payout {
+        cfg.max_payout = max_payout;
+    }
+    if let Some(quorum) = msg.quorum {
+        cfg.quorum = quorum;
+        cfg.validate_oracles()?;
+    }
+    if let Some(guards) = msg.oracle_guards {
+        cfg.oracle_guards = guards;
+    }
+    if let Some(resolutions) = msg.resolutions {
+        cfg.resolutions = resolutions;
+    }
+    CONFIG.save(deps.storage, &cfg)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let evt = Event::new("migrate")
+        .add_attribute("from", from.to_string())
+        .add_attribute("to", to.to_string())
+        .add_attribute("steps", steps.len().to_string())
+        .add_attribute("investments", migrated.to_string());
+    Ok(Response::new().add_event(evt))
+}
+
+fn parse_version(version: &str) -> Result<Version, ContractError> {
+    Version::parse(version).map_err(|_| ContractError::InvalidVersion(version.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::msg::{
+        Cw721ReceiveMsg, OracleKeyResponse, PauseStatusResponse, PendingOracleResponse,
+        PortfolioResponse,
+    };
+    use crate::payout::{IndexDirection, PayoutCurve, PayoutStep};
+    use crate::state::{ResolutionRange, TierTotals, TokenTotals};
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{Decimal, SubMsg, WasmMsg};
+    use cosmwasm_std::{attr, from_binary, SubMsg, WasmMsg};
+    use cw0::PaymentError;
 
     fn env_at(secs: u64) -> Env {
         let mut env = mock_env();
@@ -358,12 +1634,51 @@ mod tests {
 
     fn init_with_locations(locs: &[&str]) -> InstantiateMsg {
         InstantiateMsg {
+            admin: Some("admin".to_string()),
+            pauser: Some("pauser".to_string()),
             oracle: "oracle".to_string(),
+            oracles: vec![],
+            quorum: None,
+            oracle_keys: vec![],
+            oracle_guards: OracleGuards::default(),
             locations: locs.iter().map(|s| s.to_string()).collect(),
-            token: "token".to_string(),
-            max_investment_hex: Uint128::new(12345678),
+            resolutions: ResolutionRange::default(),
+            tokens: vec![Denom::Cw20(Addr::unchecked("token"))],
+            max_investment_hex: Uint128::new(20_000_000),
             maturity_days: 28,
+            maturity_tiers: vec![],
             measurement_window: 7,
+            max_payout: Decimal::percent(200),
+            payout_curve: PayoutCurve::Linear {},
+            index_direction: IndexDirection::LowerIsBetter,
+            early_exit_penalty: None,
+            fee_collector: None,
+        }
+    }
+
+    // funds the reserves of all accepted tokens, enough to cover any investment in the tests
+    fn fund_reserves(mut deps: DepsMut) {
+        let cfg = CONFIG.load(deps.storage).unwrap();
+        for token in cfg.tokens {
+            match token {
+                Denom::Native(denom) => execute(
+                    deps.branch(),
+                    mock_env(),
+                    mock_info("funder", &coins(100_000_000, denom)),
+                    ExecuteMsg::FundReserve {},
+                ),
+                Denom::Cw20(addr) => execute(
+                    deps.branch(),
+                    mock_env(),
+                    mock_info(addr.as_str(), &[]),
+                    ExecuteMsg::Receive(Cw20ReceiveMsg {
+                        sender: "funder".to_string(),
+                        amount: Uint128::new(100_000_000),
+                        msg: to_binary(&ReceiveMsg::FundReserve {}).unwrap(),
+                    }),
+                ),
+            }
+            .unwrap();
         }
     }
 
@@ -372,7 +1687,7 @@ mod tests {
         let mut deps = mock_dependencies();
 
         let info = mock_info("creator", &[]);
-        let msg = init_with_locations(&["8765437FFFFFFFF", "1284639ffffffff"]);
+        let msg = init_with_locations(&["872830828FFFFFF", "822837fffffffff"]);
 
         // we can just call .unwrap() to assert this was a success
         let res = instantiate(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
@@ -381,18 +1696,29 @@ mod tests {
         // it worked, let's query the state
         let res = query_config(deps.as_ref()).unwrap();
         let expected = Config {
+            admin: Some(Addr::unchecked("admin")),
+            pauser: Some(Addr::unchecked("pauser")),
             oracle: Addr::unchecked(msg.oracle),
-            token: Addr::unchecked(msg.token),
+            oracles: vec![],
+            quorum: 1,
+            oracle_guards: OracleGuards::default(),
+            resolutions: ResolutionRange::default(),
+            tokens: msg.tokens,
             max_investment_hex: msg.max_investment_hex,
             maturity_days: msg.maturity_days,
+            maturity_tiers: vec![28],
             measurement_window: msg.measurement_window,
+            max_payout: msg.max_payout,
+            payout_curve: msg.payout_curve,
+            index_direction: msg.index_direction,
+            early_exit: None,
         };
         assert_eq!(res, expected);
 
         // check out the locations
-        let info1 = query_info(deps.as_ref(), "8765437FFFFFfff".into()).unwrap();
+        let info1 = query_info(deps.as_ref(), "872830828FFFfff".into()).unwrap();
         assert_eq!(info1, InfoResponse::default());
-        let info2 = query_info(deps.as_ref(), "1284639ffFFffff".into()).unwrap();
+        let info2 = query_info(deps.as_ref(), "822837ffFFfffff".into()).unwrap();
         assert_eq!(info2, InfoResponse::default());
     }
 
@@ -405,14 +1731,70 @@ mod tests {
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap_err();
     }
 
+    #[test]
+    fn location_resolutions() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("creator", &[]);
+
+        // hex strings that are no h3 cell are rejected
+        let msg = init_with_locations(&["832830fffffffff", "8362718ffffffff"]);
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidR3("8362718ffffffff".to_string()));
+
+        // the range must be valid
+        let mut msg = init_with_locations(&["832830fffffffff"]);
+        msg.resolutions = ResolutionRange { min: 4, max: 3 };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolutionRange);
+        msg.resolutions = ResolutionRange { min: 3, max: 16 };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolutionRange);
+
+        // and locations must be within it
+        msg.resolutions = ResolutionRange { min: 5, max: 9 };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ResolutionOutOfRange {
+                hex: "832830fffffffff".to_string(),
+                min: 5,
+                max: 9
+            }
+        );
+        let mut deps = mock_dependencies();
+        msg.locations = vec!["872830828ffffff".to_string()];
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // also when adding them later
+        let add = ExecuteMsg::AddLocations {
+            locations: vec!["8a2830828007fff".to_string()],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), add).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ResolutionOutOfRange {
+                hex: "8a2830828007fff".to_string(),
+                min: 5,
+                max: 9
+            }
+        );
+        let add = ExecuteMsg::AddLocations {
+            locations: vec!["8928308280fffff".to_string()],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), add).unwrap();
+        let locations = list_locations(deps.as_ref(), None, None).unwrap().locations;
+        assert_eq!(locations.len(), 2);
+    }
+
     #[test]
     fn set_oracle_data() {
         let mut deps = mock_dependencies();
 
-        let location = "8362718ffffffff";
+        let location = "832830fffffffff";
         let info = mock_info("creator", &[]);
         let msg = init_with_locations(&[location]);
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
 
         let info = query_info(deps.as_ref(), location.into()).unwrap();
         assert_eq!(info, InfoResponse::default());
@@ -471,7 +1853,7 @@ mod tests {
         // ignore bad location
         let msg = ExecuteMsg::StoreOracle {
             values: vec![OracleValues {
-                index: "9362718FFffffff".to_string(),
+                index: "8928308280Fffff".to_string(),
                 value: Decimal::percent(1234),
                 time: time_at(20),
             }],
@@ -485,46 +1867,587 @@ mod tests {
             attrs[1],
             (
                 "failed",
-                ContractError::UnregisteredLocation("9362718ffffffff".to_string()).to_string()
+                ContractError::UnregisteredLocation("8928308280fffff".to_string()).to_string()
             )
         );
     }
 
     #[test]
-    fn check_investment() {
+    fn oracle_quorum() {
         let mut deps = mock_dependencies();
 
-        let location = "8362718ffffffff";
-        let info = mock_info("creator", &[]);
-        let msg = init_with_locations(&[location]);
-        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-
-        let info = query_info(deps.as_ref(), location.into()).unwrap();
-        assert_eq!(info, InfoResponse::default());
-
-        // cannot invest without data
-        let amount = Uint128::new(777000);
-        let payload = ReceiveMsg::Invest {
-            hex: location.to_string(),
-        };
-        let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: "investor".to_string(),
-            amount,
-            msg: to_binary(&payload).unwrap(),
-        });
-
-        let err = execute(
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.oracles = vec!["oracle2".to_string(), "oracle3".to_string()];
+        msg.quorum = Some(4);
+        let err = instantiate(
             deps.as_mut(),
-            env_at(1234),
-            mock_info("token", &[]),
-            wrapped.clone(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
         )
         .unwrap_err();
-        assert_eq!(err, ContractError::NoDataPresent);
-
-        // set this with some oracle data
-        let measurement = Measurement {
-            value: Decimal::percent(1234),
+        assert_eq!(err, ContractError::InvalidQuorum(4));
+        msg.oracles.push("oracle".to_string());
+        msg.quorum = Some(2);
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicateOracle("oracle".to_string()));
+        msg.oracles.pop();
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let submit = |deps: DepsMut, oracle: &str, value: u64, time: u64| {
+            let msg = ExecuteMsg::StoreOracle {
+                values: vec![OracleValues {
+                    index: location.to_string(),
+                    value: Decimal::percent(value),
+                    time: time_at(time),
+                }],
+            };
+            execute(deps, env_at(86400), mock_info(oracle, &[]), msg)
+        };
+        let cur_index = |deps: Deps| query_info(deps, location.into()).unwrap().cur_index;
+
+        // other addresses cannot submit
+        let err = submit(deps.as_mut(), "token", 100, 200).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // one oracle is not enough, even when submitting twice
+        let res = submit(deps.as_mut(), "oracle", 100, 200).unwrap();
+        assert!(res.events[0].attributes.contains(&attr("pending", "1")));
+        submit(deps.as_mut(), "oracle", 5000, 200).unwrap();
+        assert_eq!(cur_index(deps.as_ref()), None);
+        // an older time is also buffered
+        submit(deps.as_mut(), "oracle2", 700, 100).unwrap();
+
+        // the second oracle commits the median
+        let res = submit(deps.as_mut(), "oracle3", 1000, 200).unwrap();
+        assert!(res.events[0].attributes.contains(&attr("pending", "0")));
+        let expected = Measurement::new(Decimal::percent(3000), time_at(200));
+        assert_eq!(cur_index(deps.as_ref()), Some(expected));
+        // and the buffers up to this time are gone
+        let left = SUBMISSIONS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .count();
+        assert_eq!(left, 0);
+
+        // the third one comes too late
+        submit(deps.as_mut(), "oracle2", 9000, 200).unwrap();
+        assert_eq!(cur_index(deps.as_ref()), Some(expected));
+        // this one is buffered, until the oracle is replaced below
+        submit(deps.as_mut(), "oracle2", 100, 300).unwrap();
+
+        // an oracle can hand its place to a new address, but not to another oracle
+        let propose = ExecuteMsg::ProposeOracle {
+            oracle: "oracle3".to_string(),
+            expires: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle2", &[]),
+            propose,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicateOracle("oracle3".to_string()));
+        hand_over_oracle(deps.as_mut(), "oracle2", "oracle4");
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(
+            cfg.oracles,
+            vec![Addr::unchecked("oracle3"), Addr::unchecked("oracle4")]
+        );
+        let err = submit(deps.as_mut(), "oracle2", 900, 300).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // the buffered value of the replaced oracle does not count towards the quorum
+        let res = submit(deps.as_mut(), "oracle3", 1000, 300).unwrap();
+        assert!(res.events[0].attributes.contains(&attr("pending", "1")));
+        assert_eq!(cur_index(deps.as_ref()), Some(expected));
+        submit(deps.as_mut(), "oracle4", 2000, 300).unwrap();
+        assert_eq!(
+            cur_index(deps.as_ref()),
+            Some(Measurement::new(Decimal::percent(1500), time_at(300)))
+        );
+    }
+
+    #[test]
+    fn signed_oracle_values() {
+        use ed25519_zebra::{SigningKey as EdKey, VerificationKey};
+        use k256::ecdsa::signature::Signer;
+        use k256::ecdsa::{Signature, SigningKey};
+
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let secp = SigningKey::from_bytes(&[1u8; 32]).unwrap();
+        let secp_pubkey = Binary::from(secp.verifying_key().to_bytes().to_vec());
+        let ed = EdKey::from([7u8; 32]);
+        let ed_pubkey = Binary::from(<[u8; 32]>::from(VerificationKey::from(&ed)).to_vec());
+
+        let mut msg = init_with_locations(&[location]);
+        msg.oracles = vec!["vendor".to_string()];
+        msg.quorum = Some(2);
+        msg.oracle_keys = vec![RegisteredKey {
+            oracle: "other".to_string(),
+            key: OracleKey::Secp256k1 {
+                pubkey: secp_pubkey.clone(),
+            },
+        }];
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NotOracle("other".to_string()));
+        msg.oracle_keys[0].oracle = "vendor".to_string();
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let values = |value: u64, time: u64| {
+            vec![OracleValues {
+                index: location.to_string(),
+                value: Decimal::percent(value),
+                time: time_at(time),
+            }]
+        };
+        let relay =
+            |deps: DepsMut, oracle: &str, nonce: u64, values: Vec<OracleValues>, sig: Vec<u8>| {
+                let msg = ExecuteMsg::StoreSignedOracle {
+                    oracle: oracle.to_string(),
+                    nonce,
+                    values,
+                    signature: sig.into(),
+                };
+                execute(deps, env_at(86400), mock_info("relayer", &[]), msg)
+            };
+        let contract = mock_env().contract.address;
+        let vendor = Addr::unchecked("vendor");
+        let oracle = Addr::unchecked("oracle");
+        let secp_sign = |oracle: &Addr, nonce: u64, values: &[OracleValues]| -> Vec<u8> {
+            let payload = signed_payload(&contract, oracle, nonce, values).unwrap();
+            let sig: Signature = secp.sign(&payload);
+            sig.as_ref().to_vec()
+        };
+
+        // anyone can relay the vendor's values
+        let vals = values(500, 200);
+        let sig = secp_sign(&vendor, 1, &vals);
+        relay(deps.as_mut(), "vendor", 1, vals.clone(), sig.clone()).unwrap();
+        // but not replay them
+        let err = relay(deps.as_mut(), "vendor", 1, vals.clone(), sig.clone()).unwrap_err();
+        assert_eq!(err, ContractError::StaleNonce(1));
+        // nor change the values or the nonce
+        let err = relay(deps.as_mut(), "vendor", 2, vals.clone(), sig).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSignature);
+        let sig = secp_sign(&vendor, 2, &vals);
+        let err = relay(deps.as_mut(), "vendor", 2, values(900, 200), sig).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSignature);
+        // or use a signature for another contract or oracle
+        let other = Addr::unchecked("other-contract");
+        let sig: Signature = secp.sign(&signed_payload(&other, &vendor, 2, &vals).unwrap());
+        let err = relay(
+            deps.as_mut(),
+            "vendor",
+            2,
+            vals.clone(),
+            sig.as_ref().to_vec(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidSignature);
+        let sig = secp_sign(&oracle, 2, &vals);
+        let err = relay(deps.as_mut(), "vendor", 2, vals.clone(), sig.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSignature);
+        // the other oracle has no key yet
+        let err = relay(deps.as_mut(), "oracle", 2, vals.clone(), sig).unwrap_err();
+        assert_eq!(err, ContractError::NoOracleKey("oracle".to_string()));
+
+        // only the oracle itself registers its key, not even the admin
+        let set_key = |key| ExecuteMsg::SetOracleKey {
+            oracle: "oracle".to_string(),
+            key,
+        };
+        let ed_key = OracleKey::Ed25519 { pubkey: ed_pubkey };
+        for sender in ["relayer", "admin"].iter() {
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                set_key(Some(ed_key.clone())),
+            )
+            .unwrap_err();
+            assert_eq!(err, ContractError::Unauthorized {});
+        }
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            set_key(Some(OracleKey::Ed25519 {
+                pubkey: secp_pubkey.clone(),
+            })),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidOracleKey);
+        // nor can it share the key of another oracle, making up the quorum with one signer
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            set_key(Some(OracleKey::Secp256k1 {
+                pubkey: secp_pubkey,
+            })),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicateOracleKey("vendor".to_string()));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            set_key(Some(ed_key.clone())),
+        )
+        .unwrap();
+        let res: OracleKeyResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::OracleKey {
+                    oracle: "oracle".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.key, Some(ed_key));
+        assert_eq!(res.last_nonce, 0);
+
+        // the second signed value reaches the quorum, nonces count per oracle
+        let vals = values(700, 200);
+        let sig = ed.sign(&signed_payload(&contract, &oracle, 1, &vals).unwrap());
+        relay(
+            deps.as_mut(),
+            "oracle",
+            1,
+            vals,
+            <[u8; 64]>::from(sig).to_vec(),
+        )
+        .unwrap();
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(
+            info.cur_index,
+            Some(Measurement::new(Decimal::percent(600), time_at(200)))
+        );
+
+        // the admin can remove a compromised key, but others cannot
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer", &[]),
+            set_key(None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            set_key(None),
+        )
+        .unwrap();
+        assert!(res.events[0].attributes.contains(&attr("action", "remove")));
+        let vals = values(800, 300);
+        let sig = ed.sign(&signed_payload(&contract, &oracle, 2, &vals).unwrap());
+        let err = relay(
+            deps.as_mut(),
+            "oracle",
+            2,
+            vals,
+            <[u8; 64]>::from(sig).to_vec(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoOracleKey("oracle".to_string()));
+
+        // handing over the oracle removes its key
+        hand_over_oracle(deps.as_mut(), "oracle", "new-oracle");
+        assert_eq!(
+            ORACLE_KEYS
+                .may_load(&deps.storage, &Addr::unchecked("oracle"))
+                .unwrap(),
+            None
+        );
+    }
+
+    fn hand_over_oracle(mut deps: DepsMut, from: &str, to: &str) {
+        let propose = ExecuteMsg::ProposeOracle {
+            oracle: to.to_string(),
+            expires: None,
+        };
+        execute(deps.branch(), mock_env(), mock_info(from, &[]), propose).unwrap();
+        let accept = ExecuteMsg::AcceptOracle {};
+        execute(deps, mock_env(), mock_info(to, &[]), accept).unwrap();
+    }
+
+    #[test]
+    fn oracle_handover() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let propose = |oracle: &str, expires| ExecuteMsg::ProposeOracle {
+            oracle: oracle.to_string(),
+            expires,
+        };
+        let pending = |deps: Deps| -> Option<PendingOracle> {
+            let msg = QueryMsg::PendingOracle {
+                oracle: "oracle".to_string(),
+            };
+            let res: PendingOracleResponse =
+                from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.pending
+        };
+        let accept = |deps: DepsMut, sender: &str, secs: u64| {
+            execute(
+                deps,
+                env_at(secs),
+                mock_info(sender, &[]),
+                ExecuteMsg::AcceptOracle {},
+            )
+        };
+
+        // only the oracle proposes, never with a past expiry
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            propose("typo", None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let past = Some(Expiration::AtHeight(mock_env().block.height));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            propose("typo", past),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Expired);
+
+        // a typo does not take effect, and can be cancelled
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            propose("typo", None),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "propose-oracle");
+        assert_eq!(query_config(deps.as_ref()).unwrap().oracle, "oracle");
+        assert_eq!(pending(deps.as_ref()).unwrap().oracle, "typo");
+        let err = accept(deps.as_mut(), "someone", 0).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingOracle);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            ExecuteMsg::CancelOracle {},
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "cancel-oracle");
+        assert_eq!(pending(deps.as_ref()), None);
+        let err = accept(deps.as_mut(), "typo", 0).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingOracle);
+
+        // proposals expire, after one week by default
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            propose("new-oracle", None),
+        )
+        .unwrap();
+        let err = accept(deps.as_mut(), "new-oracle", 8 * 86400).unwrap_err();
+        assert_eq!(err, ContractError::Expired);
+
+        // accepted in time, the new oracle takes over
+        let res = accept(deps.as_mut(), "new-oracle", 6 * 86400).unwrap();
+        assert_eq!(res.events[0].ty, "accept-oracle");
+        assert!(res.events[0]
+            .attributes
+            .contains(&attr("previous", "oracle")));
+        assert_eq!(query_config(deps.as_ref()).unwrap().oracle, "new-oracle");
+        assert_eq!(pending(deps.as_ref()), None);
+        let store = ExecuteMsg::StoreOracle {
+            values: vec![OracleValues {
+                index: location.to_string(),
+                value: Decimal::percent(1000),
+                time: time_at(200),
+            }],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("oracle", &[]),
+            store.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("new-oracle", &[]),
+            store,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn oracle_guards() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.oracle_guards = OracleGuards {
+            max_deviation: Some(Decimal::percent(50)),
+            min_interval: Some(3600),
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let store = |deps: DepsMut, value: u64, time: u64| {
+            let msg = ExecuteMsg::StoreOracle {
+                values: vec![OracleValues {
+                    index: location.to_string(),
+                    value: Decimal::percent(value),
+                    time: time_at(time),
+                }],
+            };
+            let res = execute(deps, env_at(86400), mock_info("oracle", &[]), msg).unwrap();
+            res.events[0]
+                .attributes
+                .iter()
+                .find(|a| a.key == "failed")
+                .map(|a| a.value.clone())
+        };
+
+        // anything goes for the first value
+        assert_eq!(store(deps.as_mut(), 1000, 0), None);
+        // too soon
+        assert_eq!(
+            store(deps.as_mut(), 1100, 1800),
+            Some(
+                ContractError::UpdateTooSoon {
+                    interval: 1800,
+                    min_interval: 3600
+                }
+                .to_string()
+            )
+        );
+        // too far off, either way
+        for value in [1501, 499] {
+            assert_eq!(
+                store(deps.as_mut(), value, 3600),
+                Some(
+                    ContractError::DeviationTooLarge {
+                        value: Decimal::percent(value),
+                        previous: Decimal::percent(1000),
+                    }
+                    .to_string()
+                )
+            );
+        }
+        assert_eq!(store(deps.as_mut(), 1500, 3600), None);
+
+        // only the admin sets the range
+        let range = ExecuteMsg::SetValueRange {
+            hex: location.to_string(),
+            range: Some(ValueRange {
+                min: Decimal::percent(1000),
+                max: Decimal::percent(2000),
+            }),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            range.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let invalid = ExecuteMsg::SetValueRange {
+            hex: location.to_string(),
+            range: Some(ValueRange {
+                min: Decimal::percent(3000),
+                max: Decimal::percent(2000),
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), invalid).unwrap_err();
+        assert_eq!(err, ContractError::InvalidValueRange);
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), range).unwrap();
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.value_range.unwrap().max, Decimal::percent(2000));
+
+        assert_eq!(
+            store(deps.as_mut(), 2100, 7200),
+            Some(ContractError::ValueOutOfRange(Decimal::percent(2100)).to_string())
+        );
+
+        // lifting the deviation limit still keeps the range
+        let guards = ExecuteMsg::SetOracleGuards {
+            guards: OracleGuards::default(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), guards).unwrap();
+        assert_eq!(store(deps.as_mut(), 1000, 7200), None);
+        assert!(store(deps.as_mut(), 100, 7300).is_some());
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(
+            info.cur_index,
+            Some(Measurement::new(Decimal::percent(1000), time_at(7200)))
+        );
+    }
+
+    #[test]
+    fn check_investment() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info, InfoResponse::default());
+
+        // cannot invest without data
+        let amount = Uint128::new(777000);
+        let payload = ReceiveMsg::Invest {
+            hex: location.to_string(),
+            maturity_days: None,
+        };
+        let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "investor".to_string(),
+            amount,
+            msg: to_binary(&payload).unwrap(),
+        });
+
+        let err = execute(
+            deps.as_mut(),
+            env_at(1234),
+            mock_info("token", &[]),
+            wrapped.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoDataPresent);
+
+        // set this with some oracle data
+        let measurement = Measurement {
+            value: Decimal::percent(1234),
             time: time_at(20),
         };
         let oracle = ExecuteMsg::StoreOracle {
@@ -557,15 +2480,26 @@ mod tests {
             env_at(6000),
             "investor".into(),
             Some(location.into()),
+            None,
+            None,
+        )
+        .unwrap();
+        let invests2 = list_investments(
+            deps.as_ref(),
+            env_at(6000),
+            "investor".into(),
+            None,
+            None,
+            None,
         )
         .unwrap();
-        let invests2 =
-            list_investments(deps.as_ref(), env_at(6000), "investor".into(), None).unwrap();
         assert_eq!(invests, invests2);
         assert_eq!(invests.investments.len(), 1);
         let invest = invests.investments.pop().unwrap();
         let mut expected = InvestmentResponse {
+            id: 1,
             hex: location.to_string(),
+            token: Denom::Cw20(Addr::unchecked("token")),
             amount,
             baseline_index: measurement.value,
             latest_index: measurement,
@@ -600,6 +2534,7 @@ mod tests {
         let amount2 = Uint128::new(12345678);
         let payload = ReceiveMsg::Invest {
             hex: location.to_string(),
+            maturity_days: None,
         };
         let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
             sender: "investor".to_string(),
@@ -620,7 +2555,9 @@ mod tests {
 
         // the other one shows original values
         let expected2 = InvestmentResponse {
+            id: 2,
             hex: location.to_string(),
+            token: Denom::Cw20(Addr::unchecked("token")),
             amount: amount2,
             baseline_index: measurement2.value,
             latest_index: measurement2,
@@ -629,8 +2566,15 @@ mod tests {
             invested: time_at(2 * 86400),
             maturity_date: time_at(30 * 86400),
         };
-        let invests =
-            list_investments(deps.as_ref(), env_at(2 * 86400), "investor".into(), None).unwrap();
+        let invests = list_investments(
+            deps.as_ref(),
+            env_at(2 * 86400),
+            "investor".into(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         assert_eq!(invests.investments.len(), 2);
         assert_eq!(invests.investments, vec![expected, expected2]);
     }
@@ -639,11 +2583,12 @@ mod tests {
     fn withdraw_happy_path() {
         let mut deps = mock_dependencies();
 
-        let location = "8362718ffffffff";
-        let location2 = "9362718ffffffff";
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
         let info = mock_info("creator", &[]);
         let msg = init_with_locations(&[location, location2]);
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
 
         // oracle info for one
         let oracle = ExecuteMsg::StoreOracle {
@@ -665,6 +2610,7 @@ mod tests {
         let amount = Uint128::new(808000);
         let payload = ReceiveMsg::Invest {
             hex: location.to_string(),
+            maturity_days: None,
         };
         let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
             sender: "investor".to_string(),
@@ -695,11 +2641,25 @@ mod tests {
         )
         .unwrap();
 
-        let invests = list_investments(deps.as_ref(), mock_env(), "investor".into(), None).unwrap();
+        let invests = list_investments(
+            deps.as_ref(),
+            mock_env(),
+            "investor".into(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         assert_eq!(invests.investments.len(), 1);
 
         // now withdrawl works
-        let withdraw = ExecuteMsg::Withdraw {};
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
         let res = execute(
             deps.as_mut(),
             env_at(35 * 86400),
@@ -723,11 +2683,25 @@ mod tests {
             })]
         );
 
-        let invests = list_investments(deps.as_ref(), mock_env(), "investor".into(), None).unwrap();
+        let invests = list_investments(
+            deps.as_ref(),
+            mock_env(),
+            "investor".into(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         assert_eq!(invests.investments.len(), 0);
 
         // cannot withdraw again, no investments
-        let withdraw = ExecuteMsg::Withdraw {};
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
         let res = execute(
             deps.as_mut(),
             env_at(35 * 86400),
@@ -738,71 +2712,1988 @@ mod tests {
         assert_eq!(res.messages, vec![]);
     }
 
-    #[test]
-    fn withdraw_error_cases() {
+    fn set_oracle(deps: DepsMut, hex: &str, value: Decimal, time: u64, now: u64) {
+        let oracle = ExecuteMsg::StoreOracle {
+            values: vec![OracleValues {
+                index: hex.to_string(),
+                value,
+                time: time_at(time),
+            }],
+        };
+        execute(deps, env_at(now), mock_info("oracle", &[]), oracle).unwrap();
+    }
+
+    fn invest_in(
+        deps: DepsMut,
+        investor: &str,
+        hex: &str,
+        amount: u128,
+        now: u64,
+    ) -> Result<Response, ContractError> {
+        let payload = ReceiveMsg::Invest {
+            hex: hex.to_string(),
+            maturity_days: None,
+        };
+        let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: investor.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&payload).unwrap(),
+        });
+        execute(deps, env_at(now), mock_info("token", &[]), wrapped)
+    }
+
+    #[test]
+    fn withdraw_settles_at_maturity() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 600000, 2 * 86400).unwrap();
+
+        // first measurement after maturity (day 30) halves the index
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(500),
+            31 * 86400,
+            31 * 86400,
+        );
+        // a later measurement doubles it, investor waits for that one
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(2000),
+            34 * 86400,
+            34 * 86400,
+        );
+
+        // query reports payout from the measurement at maturity
+        let invests = list_investments(
+            deps.as_ref(),
+            env_at(35 * 86400),
+            "investor".into(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(invests.investments[0].can_withdraw);
+        assert_eq!(
+            invests.investments[0].withdraw_amount,
+            Uint128::new(1200000)
+        );
+
+        // withdrawing later still pays out 2x, not 0.5x
+        let res = execute(
+            deps.as_mut(),
+            env_at(35 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        let expected = Cw20ExecuteMsg::Transfer {
+            recipient: "investor".to_string(),
+            amount: Uint128::new(1200000),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&expected).unwrap(),
+                funds: vec![]
+            })]
+        );
+    }
+
+    #[test]
+    fn max_investment_per_hex() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+
+        let capacity = query_capacity(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(
+            capacity,
+            CapacityResponse {
+                max_investment: Uint128::new(20_000_000),
+                current_invested: Uint128::zero(),
+                remaining: Uint128::new(20_000_000),
+            }
+        );
+
+        invest_in(deps.as_mut(), "investor", location, 15_000_000, 86400).unwrap();
+        let capacity = query_capacity(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(capacity.current_invested, Uint128::new(15_000_000));
+        assert_eq!(capacity.remaining, Uint128::new(5_000_000));
+
+        // too much for what is left
+        let err = invest_in(deps.as_mut(), "other", location, 5_000_001, 86400).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ExceedsMaxInvestment {
+                remaining: Uint128::new(5_000_000)
+            }
+        );
+
+        // exactly filling it up is fine, then it is full
+        invest_in(deps.as_mut(), "other", location, 5_000_000, 86400).unwrap();
+        let capacity = query_capacity(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(capacity.remaining, Uint128::zero());
+        let err = invest_in(deps.as_mut(), "investor", location, 1, 86400).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ExceedsMaxInvestment {
+                remaining: Uint128::zero()
+            }
+        );
+    }
+
+    #[test]
+    fn paginate_locations_and_investments() {
+        let mut deps = mock_dependencies();
+
+        let hexes = [
+            "832830fffffffff",
+            "832831fffffffff",
+            "832832fffffffff",
+            "832833fffffffff",
+        ];
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&hexes);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        // invest twice in each location
+        for hex in hexes {
+            set_oracle(deps.as_mut(), hex, Decimal::percent(1000), 200, 86400);
+            invest_in(deps.as_mut(), "investor", hex, 1000, 86400).unwrap();
+            invest_in(deps.as_mut(), "investor", hex, 2000, 86400).unwrap();
+        }
+
+        // all locations, in order
+        let all = list_locations(deps.as_ref(), None, None).unwrap();
+        assert_eq!(all.locations.len(), 4);
+        let listed: Vec<_> = all.locations.iter().map(|l| l.hex.as_str()).collect();
+        assert_eq!(listed, hexes);
+        assert_eq!(all.locations[0].current_invested, Uint128::new(3000));
+        assert_eq!(all.locations[0].current_investments, 2);
+        assert_eq!(
+            all.locations[0].cur_index,
+            Some(Measurement::new(Decimal::percent(1000), time_at(200)))
+        );
+
+        // page through them
+        let page = list_locations(deps.as_ref(), None, Some(3)).unwrap();
+        assert_eq!(page.locations.len(), 3);
+        let last = page.locations[2].hex.clone();
+        let page = list_locations(deps.as_ref(), Some(last), Some(3)).unwrap();
+        assert_eq!(page.locations.len(), 1);
+        assert_eq!(page.locations[0].hex, hexes[3]);
+
+        // investments are paginated by id
+        let page = list_investments(
+            deps.as_ref(),
+            env_at(86400),
+            "investor".into(),
+            None,
+            None,
+            Some(3),
+        )
+        .unwrap();
+        let ids: Vec<_> = page.investments.iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(page.investments[2].hex, hexes[1]);
+        let page = list_investments(
+            deps.as_ref(),
+            env_at(86400),
+            "investor".into(),
+            None,
+            Some(3),
+            Some(3),
+        )
+        .unwrap();
+        let ids: Vec<_> = page.investments.iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![4, 5, 6]);
+
+        // also when filtering on one hex
+        let page = list_investments(
+            deps.as_ref(),
+            env_at(86400),
+            "investor".into(),
+            Some(hexes[2].to_uppercase()),
+            None,
+            None,
+        )
+        .unwrap();
+        let ids: Vec<_> = page.investments.iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![5, 6]);
+        let page = list_investments(
+            deps.as_ref(),
+            env_at(86400),
+            "investor".into(),
+            Some(hexes[2].to_string()),
+            Some(5),
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(page.investments.len(), 1);
+        assert_eq!(page.investments[0].id, 6);
+        assert_eq!(page.investments[0].amount, Uint128::new(2000));
+    }
+
+    #[test]
+    fn portfolio_totals() {
+        let mut deps = mock_dependencies();
+
+        let loc1 = "832830fffffffff";
+        let loc2 = "8928308280fffff";
+        let msg = init_with_locations(&[loc1, loc2]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        let portfolio = |deps: Deps, secs: u64| {
+            let msg = QueryMsg::Portfolio {
+                investor: "investor".to_string(),
+                start_after: None,
+                limit: None,
+            };
+            let res: PortfolioResponse =
+                from_binary(&query(deps, env_at(secs), msg).unwrap()).unwrap();
+            res
+        };
+        assert_eq!(portfolio(deps.as_ref(), 0), PortfolioResponse::default());
+
+        set_oracle(deps.as_mut(), loc1, Decimal::percent(1000), 200, 86400);
+        set_oracle(deps.as_mut(), loc2, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", loc1, 10_000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", loc2, 5_000, 86400).unwrap();
+        set_oracle(
+            deps.as_mut(),
+            loc1,
+            Decimal::percent(500),
+            20 * 86400,
+            20 * 86400,
+        );
+        invest_in(deps.as_mut(), "investor", loc1, 2_000, 20 * 86400).unwrap();
+        invest_in(deps.as_mut(), "other", loc1, 7_000, 20 * 86400).unwrap();
+
+        // none mature yet, all projected at the current index
+        let res = portfolio(deps.as_ref(), 20 * 86400);
+        assert_eq!(res.investments, 3);
+        assert_eq!(res.mature, 0);
+        assert_eq!(res.next_maturity, Some(time_at(29 * 86400)));
+        assert_eq!(
+            res.tokens,
+            vec![PortfolioTotals {
+                token: Denom::Cw20(Addr::unchecked("token")),
+                principal: Uint128::new(17_000),
+                claimable: Uint128::zero(),
+                projected: Uint128::new(27_000),
+            }]
+        );
+
+        // the first settles, the second waits for data
+        set_oracle(
+            deps.as_mut(),
+            loc1,
+            Decimal::percent(800),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = portfolio(deps.as_ref(), 30 * 86400);
+        assert_eq!(res.investments, 3);
+        assert_eq!(res.mature, 2);
+        assert_eq!(res.next_maturity, Some(time_at(48 * 86400)));
+        let totals = &res.tokens[0];
+        assert_eq!(totals.principal, Uint128::new(17_000));
+        assert_eq!(totals.claimable, Uint128::new(12_500));
+        // 12_500 settled + 5_000 at loc2's index + 2_000 * 500 / 800
+        assert_eq!(totals.projected, Uint128::new(18_750));
+        assert_eq!(res.last_id, None);
+
+        // the totals can be added up over pages
+        let page = |start_after, limit| {
+            let msg = QueryMsg::Portfolio {
+                investor: "investor".to_string(),
+                start_after,
+                limit: Some(limit),
+            };
+            let res: PortfolioResponse =
+                from_binary(&query(deps.as_ref(), env_at(30 * 86400), msg).unwrap()).unwrap();
+            res
+        };
+        let first = page(None, 2);
+        assert_eq!(first.investments, 2);
+        assert_eq!(first.mature, 2);
+        assert_eq!(first.next_maturity, None);
+        assert_eq!(first.tokens[0].principal, Uint128::new(15_000));
+        assert_eq!(first.last_id, Some(2));
+        let second = page(first.last_id, 2);
+        assert_eq!(second.investments, 1);
+        assert_eq!(second.next_maturity, Some(time_at(48 * 86400)));
+        assert_eq!(second.tokens[0].principal, Uint128::new(2_000));
+        assert_eq!(second.last_id, None);
+    }
+
+    #[test]
+    fn simulate_invest_and_withdraw() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        let simulate = |deps: Deps, hex: &str, secs: u64| {
+            let msg = QueryMsg::SimulateInvest {
+                hex: hex.to_string(),
+                amount: Uint128::new(5000),
+                token: None,
+                maturity_days: None,
+            };
+            query(deps, env_at(secs), msg)
+                .and_then(|bin| Ok(from_binary::<SimulateInvestResponse>(&bin)?))
+        };
+
+        // same errors as investing
+        let err = simulate(deps.as_ref(), "8928308280fffff", 86400).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnregisteredLocation("8928308280fffff".to_string())
+        );
+        let err = simulate(deps.as_ref(), location, 86400).unwrap_err();
+        assert_eq!(err, ContractError::NoDataPresent);
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        let err = simulate(deps.as_ref(), location, 20 * 86400).unwrap_err();
+        assert_eq!(err, ContractError::DataTooOld { days: 7 });
+
+        let res = simulate(deps.as_ref(), location, 86400).unwrap();
+        assert_eq!(
+            res,
+            SimulateInvestResponse {
+                hex: location.to_string(),
+                baseline_index: Measurement::new(Decimal::percent(1000), time_at(200)),
+                maturity_days: 28,
+                maturity_date: time_at(29 * 86400),
+                max_payout: Uint128::new(10_000),
+                remaining_capacity: Uint128::new(19_995_000),
+            }
+        );
+        // nothing was written
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.total_invested, Uint128::zero());
+
+        invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap();
+        let simulate_withdraw = |deps: Deps, secs: u64| {
+            let msg = QueryMsg::SimulateWithdraw {
+                investor: "investor".to_string(),
+            };
+            let res: SimulateWithdrawResponse =
+                from_binary(&query(deps, env_at(secs), msg).unwrap()).unwrap();
+            res
+        };
+        let res = simulate_withdraw(deps.as_ref(), 86400);
+        assert_eq!(res.investments, vec![]);
+        assert_eq!(res.totals, vec![]);
+
+        // matches the actual withdrawal
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(800),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = simulate_withdraw(deps.as_ref(), 30 * 86400);
+        let token = Denom::Cw20(Addr::unchecked("token"));
+        assert_eq!(
+            res.investments,
+            vec![SimulatedWithdrawal {
+                id: 1,
+                hex: location.to_string(),
+                token: token.clone(),
+                amount: Uint128::new(5000),
+                payout: Uint128::new(6250),
+            }]
+        );
+        assert_eq!(
+            res.totals,
+            vec![TokenAmount {
+                token,
+                amount: Uint128::new(6250),
+            }]
+        );
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw,
+        )
+        .unwrap();
+        assert!(res
+            .events
+            .iter()
+            .any(|e| e.ty == "withdraw-total" && e.attributes.contains(&attr("amount", "6250"))));
+    }
+
+    #[test]
+    fn query_investment_by_id() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location, location2]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        set_oracle(deps.as_mut(), location2, Decimal::percent(500), 200, 86400);
+
+        // ids are assigned in order over all investors and hexes
+        let res = invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+        assert_eq!(res.events[0].attributes[0], ("investment_id", "1"));
+        let res = invest_in(deps.as_mut(), "other", location2, 2000, 86400).unwrap();
+        assert_eq!(res.events[0].attributes[0], ("investment_id", "2"));
+        let res = invest_in(deps.as_mut(), "investor", location2, 3000, 2 * 86400).unwrap();
+        assert_eq!(res.events[0].attributes[0], ("investment_id", "3"));
+
+        let info = query_investment(deps.as_ref(), env_at(2 * 86400), 2).unwrap();
+        assert_eq!(info.investor, Addr::unchecked("other"));
+        assert_eq!(info.investment.id, 2);
+        assert_eq!(info.investment.hex, location2);
+        assert_eq!(info.investment.amount, Uint128::new(2000));
+
+        let info = query_investment(deps.as_ref(), env_at(2 * 86400), 3).unwrap();
+        assert_eq!(info.investor, Addr::unchecked("investor"));
+        assert_eq!(info.investment.amount, Uint128::new(3000));
+        assert_eq!(info.investment.invested, time_at(2 * 86400));
+
+        let err = query_investment(deps.as_ref(), env_at(2 * 86400), 4).unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(4));
+
+        // once withdrawn, it is no longer found
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "withdraw");
+        assert_eq!(res.events[0].attributes[0], ("investment_id", "1"));
+        let err = query_investment(deps.as_ref(), env_at(30 * 86400), 1).unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(1));
+        query_investment(deps.as_ref(), env_at(30 * 86400), 3).unwrap();
+    }
+
+    #[test]
+    fn admin_manages_locations() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        // only admin can add locations
+        let add = ExecuteMsg::AddLocations {
+            locations: vec![location2.to_uppercase()],
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("oracle", &[]),
+            add.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), add).unwrap();
+        assert_eq!(res.events[0].ty, "add-location");
+        assert_eq!(res.events[0].attributes[0], ("hex", location2));
+        let info = query_info(deps.as_ref(), location2.into()).unwrap();
+        assert_eq!(info, InfoResponse::default());
+
+        // cannot add twice, or add invalid hexes
+        let add = ExecuteMsg::AddLocations {
+            locations: vec![location.to_string()],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), add).unwrap_err();
+        assert_eq!(err, ContractError::LocationExists(location.to_string()));
+        let add = ExecuteMsg::AddLocations {
+            locations: vec!["foobar".to_string()],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), add).unwrap_err();
+        assert_eq!(err, ContractError::InvalidR3("foobar".to_string()));
+
+        // new location can be invested in
+        set_oracle(deps.as_mut(), location2, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location2, 1000, 86400).unwrap();
+
+        // retire it
+        let retire = ExecuteMsg::RetireLocation {
+            hex: location2.to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("investor", &[]),
+            retire.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            retire.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "retire-location");
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), retire).unwrap_err();
+        assert_eq!(err, ContractError::LocationRetired(location2.to_string()));
+        assert!(query_info(deps.as_ref(), location2.into()).unwrap().retired);
+
+        // no new investments, but oracle still updates and existing ones settle
+        let err = invest_in(deps.as_mut(), "investor", location2, 1000, 86400).unwrap_err();
+        assert_eq!(err, ContractError::LocationRetired(location2.to_string()));
+        set_oracle(
+            deps.as_mut(),
+            location2,
+            Decimal::percent(500),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        // re-open it
+        let reopen = ExecuteMsg::ReopenLocation {
+            hex: location2.to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            reopen.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "reopen-location");
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), reopen).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::LocationNotRetired(location2.to_string())
+        );
+        invest_in(deps.as_mut(), "investor", location2, 1000, 30 * 86400).unwrap();
+
+        // unknown locations cannot be retired
+        let retire = ExecuteMsg::RetireLocation {
+            hex: "822837fffffffff".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), retire).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnregisteredLocation("822837fffffffff".to_string())
+        );
+    }
+
+    #[test]
+    fn no_admin_means_fixed_locations() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("creator", &[]);
+        let mut msg = init_with_locations(&["832830fffffffff"]);
+        msg.admin = None;
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        let add = ExecuteMsg::AddLocations {
+            locations: vec!["8928308280fffff".to_string()],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), add).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // an admin can be set on migration
+        let msg = MigrateMsg {
+            admin: Some("admin".to_string()),
+            ..MigrateMsg::default()
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(cfg.admin, Some(Addr::unchecked("admin")));
+    }
+
+    #[test]
+    fn invest_and_withdraw_native() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let mut msg = init_with_locations(&[location]);
+        msg.tokens = vec![Denom::Native("ustable".to_string())];
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+
+        let invest = ExecuteMsg::Invest {
+            hex: location.to_string(),
+            maturity_days: None,
+        };
+        // must send funds
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &[]),
+            invest.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Payment(PaymentError::NoFunds {}));
+        // of the right denom
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &coins(5000, "uother")),
+            invest.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidToken("uother".to_string()));
+        // cw20 is not accepted
+        let err = invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap_err();
+        assert_eq!(err, ContractError::InvalidToken("token".to_string()));
+
+        // this works
+        let res = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &coins(5000, "ustable")),
+            invest,
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "invest");
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(5000));
+
+        // index halves, payout doubles in native tokens
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(500),
+            30 * 86400,
+            30 * 86400,
+        );
+        // native tokens cannot be delivered with a hook
+        let err = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: Some("vault".to_string()),
+                msg: Some(Binary::from(b"{}")),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::CannotSendNative("ustable".to_string()));
+        // but sent to another address
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: Some("vault".to_string()),
+                msg: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "vault".to_string(),
+                amount: coins(10000, "ustable"),
+            })]
+        );
+    }
+
+    #[test]
+    fn withdraw_to_contract() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap();
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+
+        // delivered into the vault contract with a hook
+        let hook = Binary::from(br#"{"deposit":{}}"#);
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: Some("vault".to_string()),
+                msg: Some(hook.clone()),
+            },
+        )
+        .unwrap();
+        let expected = Cw20ExecuteMsg::Send {
+            contract: "vault".to_string(),
+            amount: Uint128::new(5000),
+            msg: hook,
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&expected).unwrap(),
+                funds: vec![]
+            })]
+        );
+        let total = res
+            .events
+            .iter()
+            .find(|e| e.ty == "withdraw-total")
+            .unwrap();
+        assert!(total.attributes.contains(&attr("recipient", "vault")));
+    }
+
+    #[test]
+    fn multiple_tokens() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let info = mock_info("creator", &[]);
+        let mut msg = init_with_locations(&[location]);
+        let cw20 = Denom::Cw20(Addr::unchecked("token"));
+        let native = Denom::Native("ustable".to_string());
+        msg.tokens = vec![cw20.clone(), native.clone()];
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+
+        // invest in both tokens
+        invest_in(deps.as_mut(), "investor", location, 3000, 86400).unwrap();
+        let invest = ExecuteMsg::Invest {
+            hex: location.to_string(),
+            maturity_days: None,
+        };
+        execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &coins(5000, "ustable")),
+            invest.clone(),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("other", &coins(2000, "ustable")),
+            invest,
+        )
+        .unwrap();
+
+        // tracked per token
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(10000));
+        assert_eq!(
+            info.tokens,
+            vec![
+                TokenTotals {
+                    token: cw20.clone(),
+                    total_invested: Uint128::new(3000),
+                    current_invested: Uint128::new(3000),
+                },
+                TokenTotals {
+                    token: native.clone(),
+                    total_invested: Uint128::new(7000),
+                    current_invested: Uint128::new(7000),
+                }
+            ]
+        );
+        let invests = list_investments(
+            deps.as_ref(),
+            env_at(86400),
+            "investor".into(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(invests.investments[0].token, cw20);
+        assert_eq!(invests.investments[1].token, native);
+
+        // withdraw pays each in its own token
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(500),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        let expected = Cw20ExecuteMsg::Transfer {
+            recipient: "investor".to_string(),
+            amount: Uint128::new(6000),
+        };
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&expected).unwrap(),
+                    funds: vec![]
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "investor".to_string(),
+                    amount: coins(10000, "ustable"),
+                })
+            ]
+        );
+
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(2000));
+        assert_eq!(info.tokens[0].current_invested, Uint128::zero());
+        assert_eq!(info.tokens[1].current_invested, Uint128::new(2000));
+    }
+
+    #[test]
+    fn must_accept_some_token() {
+        let mut deps = mock_dependencies();
+        let mut msg = init_with_locations(&["832830fffffffff"]);
+        msg.tokens = vec![];
+        let err =
+            instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NoTokens);
+    }
+
+    fn withdrawn_ids(res: &Response) -> Vec<String> {
+        res.events
+            .iter()
+            .filter(|e| e.ty == "withdraw")
+            .map(|e| e.attributes[0].value.clone())
+            .collect()
+    }
+
+    #[test]
+    fn withdraw_selected_investments() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location, location2]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        set_oracle(deps.as_mut(), location2, Decimal::percent(1000), 200, 86400);
+
+        invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location2, 2000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location, 3000, 86400).unwrap();
+        invest_in(deps.as_mut(), "other", location, 4000, 86400).unwrap();
+        // this one matures later
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            5 * 86400,
+            5 * 86400,
+        );
+        invest_in(deps.as_mut(), "investor", location, 5000, 5 * 86400).unwrap();
+
+        // data for the first ones to settle
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+        set_oracle(
+            deps.as_mut(),
+            location2,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+        let now = env_at(31 * 86400);
+        let withdraw = |hexes: Option<Vec<&str>>, ids: Option<Vec<u64>>, limit: Option<u32>| {
+            ExecuteMsg::Withdraw {
+                hexes: hexes.map(|h| h.into_iter().map(String::from).collect()),
+                ids,
+                limit,
+                recipient: None,
+                msg: None,
+            }
+        };
+
+        // cannot withdraw immature, unknown or others' investments by id
+        let err = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(None, Some(vec![1, 5]), None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::CannotWithdraw(5));
+        let err = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(None, Some(vec![4]), None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(4));
+        let err = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(Some(vec![location2]), Some(vec![1]), None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(1));
+
+        // nor the same one twice, which would pay it out again from the shared totals
+        let err = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(None, Some(vec![1, 3, 1]), None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicateInvestment(1));
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(13000));
+        assert_eq!(info.current_investments, 4);
+
+        // only one hex
+        let res = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(Some(vec![location2]), None, None),
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["2"]);
+
+        // only as many as the limit
+        let res = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(None, None, Some(1)),
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["1"]);
+
+        // by id
+        let res = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("investor", &[]),
+            withdraw(None, Some(vec![3]), None),
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["3"]);
+        let expected = Cw20ExecuteMsg::Transfer {
+            recipient: "investor".to_string(),
+            amount: Uint128::new(3000),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&expected).unwrap(),
+                funds: vec![]
+            })]
+        );
+
+        // nothing left to withdraw, the last is still open
+        let res = execute(
+            deps.as_mut(),
+            now,
+            mock_info("investor", &[]),
+            withdraw(None, None, None),
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![]);
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(9000));
+        assert_eq!(info.current_investments, 2);
+    }
+
+    #[test]
+    fn withdraw_without_payout() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.payout_curve = PayoutCurve::Step {
+            steps: vec![PayoutStep {
+                ratio: Decimal::percent(200),
+                multiplier: Decimal::percent(150),
+            }],
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+
+        // index unchanged, so below the first step and nothing is paid out
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![]);
+        // but the removal is still reported
+        assert_eq!(withdrawn_ids(&res), vec!["1"]);
+        assert!(res.events[0].attributes.contains(&attr("payout", "0")));
+        assert!(!res.events.iter().any(|e| e.ty == "withdraw-total"));
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_investments, 0);
+    }
+
+    #[test]
+    fn withdraw_error_cases() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let info = mock_info("creator", &[]);
+        let msg = init_with_locations(&[location, location2]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+
+        // oracle info for one
+        let oracle = ExecuteMsg::StoreOracle {
+            values: vec![OracleValues {
+                index: location.to_string(),
+                value: Decimal::percent(1234),
+                time: time_at(200),
+            }],
+        };
+        execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("oracle", &[]),
+            oracle,
+        )
+        .unwrap();
+
+        // invest there
+        let amount = Uint128::new(808000);
+        let payload = ReceiveMsg::Invest {
+            hex: location.to_string(),
+            maturity_days: None,
+        };
+        let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "investor".to_string(),
+            amount,
+            msg: to_binary(&payload).unwrap(),
+        });
+        execute(
+            deps.as_mut(),
+            env_at(2 * 86400),
+            mock_info("token", &[]),
+            wrapped,
+        )
+        .unwrap();
+
+        // withdraw too early, no op
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env_at(22 * 86400),
+            mock_info("investor", &[]),
+            withdraw,
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![]);
+
+        // withdraw later, no data, no op
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env_at(35 * 86400),
+            mock_info("investor", &[]),
+            withdraw,
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![]);
+    }
+
+    #[test]
+    fn reserve_backs_investments() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.max_payout = Decimal::percent(99);
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidMaxPayout);
+        msg.max_payout = Decimal::percent(200);
+        msg.payout_curve = PayoutCurve::Step { steps: vec![] };
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPayoutCurve(_)));
+        msg.payout_curve = PayoutCurve::Linear {};
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+
+        // nothing to back the payouts yet
+        let err = invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve("token".to_string()));
+
+        // only accepted tokens can be funded
+        let fund = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "funder".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&ReceiveMsg::FundReserve {}).unwrap(),
+        });
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("other", &[]),
+            fund.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidToken("other".to_string()));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), fund).unwrap();
+        assert_eq!(res.events[0].ty, "fund-reserve");
+
+        // at most 2x payout, so 1000 in reserve backs 1000 invested
+        invest_in(deps.as_mut(), "investor", location, 600, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location, 400, 86400).unwrap();
+        let err = invest_in(deps.as_mut(), "investor", location, 1, 86400).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve("token".to_string()));
+
+        let solvency = query_solvency(deps.as_ref()).unwrap();
+        let token = Denom::Cw20(Addr::unchecked("token"));
+        assert!(solvency.solvent);
+        assert_eq!(
+            solvency.tokens,
+            vec![TokenSolvency {
+                token: token.clone(),
+                reserve: Uint128::new(1000),
+                invested: Uint128::new(1000),
+                liabilities: Uint128::new(1000),
+                solvent: true,
+            }]
+        );
+
+        // index drops to a fifth, the gains are paid from the reserve, capped at 2x
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(200),
+            30 * 86400,
+            30 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: Some(vec![1]),
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        let total = res
+            .events
+            .iter()
+            .find(|e| e.ty == "withdraw-total")
+            .unwrap();
+        assert!(total.attributes.contains(&attr("amount", "1200")));
+
+        let solvency = query_solvency(deps.as_ref()).unwrap();
+        assert_eq!(
+            solvency.tokens,
+            vec![TokenSolvency {
+                token,
+                reserve: Uint128::new(400),
+                invested: Uint128::new(400),
+                liabilities: Uint128::new(400),
+                solvent: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn reserve_settles_net_of_batch() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let msg = init_with_locations(&[location, location2]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        set_oracle(deps.as_mut(), location2, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location2, 1000, 86400).unwrap();
+
+        // the first gains 250, the second loses 200
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(800),
+            30 * 86400,
+            30 * 86400,
+        );
+        set_oracle(
+            deps.as_mut(),
+            location2,
+            Decimal::percent(1250),
+            30 * 86400,
+            30 * 86400,
+        );
+        // with only 100 left in the reserve (as after a migration)
+        let token = Denom::Cw20(Addr::unchecked("token"));
+        let mut reserve = Reserve::load(&deps.storage, &token).unwrap();
+        reserve.reserve = Uint128::new(100);
+        reserve.save(&mut deps.storage).unwrap();
+
+        // the gain alone cannot be paid
+        let withdraw = |ids: Vec<u64>| ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: Some(ids),
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw(vec![1]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve("token".to_string()));
+
+        // but along with the loss it can, even if it comes first
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw(vec![1, 2]),
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["1", "2"]);
+        let total = res
+            .events
+            .iter()
+            .find(|e| e.ty == "withdraw-total")
+            .unwrap();
+        assert!(total.attributes.contains(&attr("amount", "2050")));
+        let reserve = Reserve::load(&deps.storage, &token).unwrap();
+        assert_eq!(reserve.reserve, Uint128::new(50));
+        assert_eq!(reserve.invested, Uint128::zero());
+    }
+
+    #[test]
+    fn sweep_defers_unfunded_gains() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let location2 = "8928308280fffff";
+        let msg = init_with_locations(&[location, location2]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        set_oracle(deps.as_mut(), location2, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location, 1000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location2, 1000, 86400).unwrap();
+
+        // the first two gain 250 each, the last loses 200
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(800),
+            30 * 86400,
+            30 * 86400,
+        );
+        set_oracle(
+            deps.as_mut(),
+            location2,
+            Decimal::percent(1250),
+            30 * 86400,
+            30 * 86400,
+        );
+        let token = Denom::Cw20(Addr::unchecked("token"));
+        let mut reserve = Reserve::load(&deps.storage, &token).unwrap();
+        reserve.reserve = Uint128::new(100);
+        reserve.save(&mut deps.storage).unwrap();
+
+        // the loss covers one of the gains that came before it, the other one waits
+        let sim = simulate_withdraw(deps.as_ref(), env_at(30 * 86400), "investor".into()).unwrap();
+        let ids = |list: &[SimulatedWithdrawal]| list.iter().map(|w| w.id).collect::<Vec<_>>();
+        assert_eq!(ids(&sim.investments), vec![1, 3]);
+        assert_eq!(ids(&sim.deferred), vec![2]);
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["1", "3"]);
+        let deferred: Vec<_> = res
+            .events
+            .iter()
+            .filter(|e| e.ty == "withdraw-deferred")
+            .collect();
+        assert_eq!(deferred.len(), 1);
+        assert!(deferred[0].attributes.contains(&attr("investment_id", "2")));
+        let total = res
+            .events
+            .iter()
+            .find(|e| e.ty == "withdraw-total")
+            .unwrap();
+        assert!(total.attributes.contains(&attr("amount", "2050")));
+        let reserve = Reserve::load(&deps.storage, &token).unwrap();
+        assert_eq!(reserve.reserve, Uint128::new(50));
+        query_investment(deps.as_ref(), env_at(30 * 86400), 2).unwrap();
+    }
+
+    #[test]
+    fn maturity_tiers() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.maturity_tiers = vec![0, 7];
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidMaturity(0));
+        msg.maturity_tiers = vec![90, 7];
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(cfg.maturity_tiers, vec![7, 28, 90]);
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+
+        let invest_for = |deps: DepsMut, amount: u128, maturity_days: Option<u64>| {
+            let payload = ReceiveMsg::Invest {
+                hex: location.to_string(),
+                maturity_days,
+            };
+            let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "investor".to_string(),
+                amount: Uint128::new(amount),
+                msg: to_binary(&payload).unwrap(),
+            });
+            execute(deps, env_at(86400), mock_info("token", &[]), wrapped)
+        };
+        let err = invest_for(deps.as_mut(), 1000, Some(14)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMaturity(14));
+        invest_for(deps.as_mut(), 1000, None).unwrap();
+        invest_for(deps.as_mut(), 2000, Some(7)).unwrap();
+        invest_for(deps.as_mut(), 3000, Some(90)).unwrap();
+        invest_for(deps.as_mut(), 4000, Some(7)).unwrap();
+
+        let short = query_investment(deps.as_ref(), env_at(86400), 2).unwrap();
+        assert_eq!(short.investment.maturity_date, time_at(8 * 86400));
+        let long = query_investment(deps.as_ref(), env_at(86400), 3).unwrap();
+        assert_eq!(long.investment.maturity_date, time_at(91 * 86400));
+
+        let tier = |days, current: u128, count| TierTotals {
+            maturity_days: days,
+            total_invested: Uint128::new(current),
+            current_invested: Uint128::new(current),
+            total_investments: count,
+            current_investments: count,
+        };
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(
+            info.tiers,
+            vec![tier(28, 1000, 1), tier(7, 6000, 2), tier(90, 3000, 1)]
+        );
+
+        // only the short ones can be withdrawn after a week
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            8 * 86400,
+            8 * 86400,
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(8 * 86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::Withdraw {
+                hexes: None,
+                ids: None,
+                limit: None,
+                recipient: None,
+                msg: None,
+            },
+        )
+        .unwrap();
+        let total = res
+            .events
+            .iter()
+            .find(|e| e.ty == "withdraw-total")
+            .unwrap();
+        assert!(total.attributes.contains(&attr("amount", "6000")));
+
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        let mut short = tier(7, 6000, 2);
+        short.current_invested = Uint128::zero();
+        short.current_investments = 0;
+        assert_eq!(
+            info.tiers,
+            vec![tier(28, 1000, 1), short, tier(90, 3000, 1)]
+        );
+    }
+
+    #[test]
+    fn exit_early() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        msg.early_exit_penalty = Some(Decimal::percent(101));
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidPenalty);
+        msg.early_exit_penalty = Some(Decimal::percent(10));
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 28_000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap();
+
+        // only the investor can exit
+        let exit = |id| ExecuteMsg::ExitEarly { id };
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("other", &[]),
+            exit(1),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(1));
+
+        // a week in (of 28 days), 3/4 of the penalty remains
+        let res = execute(
+            deps.as_mut(),
+            env_at(8 * 86400),
+            mock_info("investor", &[]),
+            exit(1),
+        )
+        .unwrap();
+        let expected = Cw20ExecuteMsg::Transfer {
+            recipient: "investor".to_string(),
+            amount: Uint128::new(25_900),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&expected).unwrap(),
+                funds: vec![]
+            })]
+        );
+        assert!(res.events[0].attributes.contains(&attr("penalty", "2100")));
+        let err = execute(
+            deps.as_mut(),
+            env_at(8 * 86400),
+            mock_info("investor", &[]),
+            exit(1),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(1));
+
+        // the location no longer counts it, and the penalty went to the reserve
+        let info = query_info(deps.as_ref(), location.into()).unwrap();
+        assert_eq!(info.current_invested, Uint128::new(5000));
+        assert_eq!(info.current_investments, 1);
+        assert_eq!(nft::query_num_tokens(deps.as_ref()).unwrap().count, 1);
+        let solvency = query_solvency(deps.as_ref()).unwrap();
+        assert_eq!(solvency.tokens[0].invested, Uint128::new(5000));
+        assert_eq!(solvency.tokens[0].reserve, Uint128::new(100_002_100));
+
+        // mature ones must be withdrawn
+        let err = execute(
+            deps.as_mut(),
+            env_at(29 * 86400),
+            mock_info("investor", &[]),
+            exit(2),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::AlreadyMature(2));
+    }
+
+    #[test]
+    fn exit_early_to_fee_collector() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let mut msg = init_with_locations(&[location]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 28_000, 86400).unwrap();
+
+        // not allowed by default
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::ExitEarly { id: 1 },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::EarlyExitDisabled);
+
+        let mut deps = mock_dependencies();
+        msg.early_exit_penalty = Some(Decimal::percent(10));
+        msg.fee_collector = Some("collector".to_string());
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 28_000, 86400).unwrap();
+
+        // right away, the full penalty goes to the collector
+        let res = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("investor", &[]),
+            ExecuteMsg::ExitEarly { id: 1 },
+        )
+        .unwrap();
+        let transfer = |recipient: &str, amount| {
+            let msg = Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount: Uint128::new(amount),
+            };
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&msg).unwrap(),
+                funds: vec![],
+            })
+        };
+        assert_eq!(
+            res.messages,
+            vec![transfer("investor", 25_200), transfer("collector", 2800)]
+        );
+        let solvency = query_solvency(deps.as_ref()).unwrap();
+        assert_eq!(solvency.tokens[0].invested, Uint128::zero());
+        assert_eq!(solvency.tokens[0].reserve, Uint128::new(100_000_000));
+    }
+
+    #[test]
+    fn investments_are_nfts() {
         let mut deps = mock_dependencies();
 
-        let location = "8362718ffffffff";
-        let location2 = "9362718ffffffff";
+        let location = "832830fffffffff";
         let info = mock_info("creator", &[]);
-        let msg = init_with_locations(&[location, location2]);
+        let msg = init_with_locations(&[location]);
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap();
+        invest_in(deps.as_mut(), "investor", location, 3000, 86400).unwrap();
 
-        // oracle info for one
-        let oracle = ExecuteMsg::StoreOracle {
-            values: vec![OracleValues {
-                index: location.to_string(),
-                value: Decimal::percent(1234),
-                time: time_at(200),
-            }],
+        let now = env_at(2 * 86400);
+        let nft = nft::query_nft_info(deps.as_ref(), "1".to_string()).unwrap();
+        assert_eq!(nft.extension.hex, location);
+        assert_eq!(nft.extension.amount, Uint128::new(5000));
+        assert_eq!(nft.extension.baseline_index, Decimal::percent(1000));
+        assert_eq!(nft.extension.maturity_date, time_at(29 * 86400));
+        assert_eq!(nft::query_num_tokens(deps.as_ref()).unwrap().count, 2);
+        let err = nft::query_nft_info(deps.as_ref(), "one".to_string()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidTokenId("one".to_string()));
+
+        // only the owner or approved may transfer
+        let transfer = |recipient: &str, token_id: &str| ExecuteMsg::TransferNft {
+            recipient: recipient.to_string(),
+            token_id: token_id.to_string(),
         };
+        let err = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("spender", &[]),
+            transfer("spender", "1"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
         execute(
             deps.as_mut(),
-            env_at(86400),
-            mock_info("oracle", &[]),
-            oracle,
+            now.clone(),
+            mock_info("investor", &[]),
+            ExecuteMsg::Approve {
+                spender: "spender".to_string(),
+                token_id: "1".to_string(),
+                expires: None,
+            },
         )
         .unwrap();
+        let owner = nft::query_owner_of(deps.as_ref(), now.clone(), "1".to_string(), None).unwrap();
+        assert_eq!(owner.owner, Addr::unchecked("investor"));
+        assert_eq!(owner.approvals.len(), 1);
 
-        // invest there
-        let amount = Uint128::new(808000);
-        let payload = ReceiveMsg::Invest {
-            hex: location.to_string(),
-        };
-        let wrapped = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: "investor".to_string(),
-            amount,
-            msg: to_binary(&payload).unwrap(),
-        });
+        // approved spender transfers, which clears the approvals
+        let res = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("spender", &[]),
+            transfer("buyer", "1"),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "transfer-nft");
+        let owner = nft::query_owner_of(deps.as_ref(), now.clone(), "1".to_string(), None).unwrap();
+        assert_eq!(owner.owner, Addr::unchecked("buyer"));
+        assert_eq!(owner.approvals, vec![]);
+        let tokens = nft::query_tokens(deps.as_ref(), "buyer".to_string(), None, None).unwrap();
+        assert_eq!(tokens.tokens, vec!["1"]);
+        let tokens = nft::query_all_tokens(deps.as_ref(), Some("1".to_string()), None).unwrap();
+        assert_eq!(tokens.tokens, vec!["2"]);
+
+        // an operator can move all of the investor's tokens, sending calls the contract
         execute(
             deps.as_mut(),
-            env_at(2 * 86400),
-            mock_info("token", &[]),
-            wrapped,
+            now.clone(),
+            mock_info("investor", &[]),
+            ExecuteMsg::ApproveAll {
+                operator: "operator".to_string(),
+                expires: None,
+            },
+        )
+        .unwrap();
+        let hook = Binary::from(br#"{"list":{}}"#);
+        let res = execute(
+            deps.as_mut(),
+            now.clone(),
+            mock_info("operator", &[]),
+            ExecuteMsg::SendNft {
+                contract: "market".to_string(),
+                token_id: "2".to_string(),
+                msg: hook.clone(),
+            },
+        )
+        .unwrap();
+        let expected = Cw721ReceiveMsg {
+            sender: "operator".to_string(),
+            token_id: "2".to_string(),
+            msg: hook,
+        }
+        .into_cosmos_msg("market".to_string())
+        .unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(expected)]);
+        let invests = list_investments(
+            deps.as_ref(),
+            now.clone(),
+            "investor".to_string(),
+            None,
+            None,
+            None,
         )
         .unwrap();
+        assert_eq!(invests.investments, vec![]);
 
-        // withdraw too early, no op
-        let withdraw = ExecuteMsg::Withdraw {};
+        // at maturity, the new owner gets the payout
+        set_oracle(
+            deps.as_mut(),
+            location,
+            Decimal::percent(1000),
+            30 * 86400,
+            30 * 86400,
+        );
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
         let res = execute(
             deps.as_mut(),
-            env_at(22 * 86400),
+            env_at(30 * 86400),
             mock_info("investor", &[]),
-            withdraw,
+            withdraw.clone(),
         )
         .unwrap();
         assert_eq!(res.messages, vec![]);
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("buyer", &[]),
+            withdraw,
+        )
+        .unwrap();
+        let expected = Cw20ExecuteMsg::Transfer {
+            recipient: "buyer".to_string(),
+            amount: Uint128::new(5000),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&expected).unwrap(),
+                funds: vec![]
+            })]
+        );
+        let err = nft::query_owner_of(deps.as_ref(), now, "1".to_string(), None).unwrap_err();
+        assert_eq!(err, ContractError::InvestmentNotFound(1));
+    }
 
-        // withdraw later, no data, no op
-        let withdraw = ExecuteMsg::Withdraw {};
+    #[test]
+    fn pause_flags() {
+        let mut deps = mock_dependencies();
+
+        let location = "832830fffffffff";
+        let msg = init_with_locations(&[location]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        fund_reserves(deps.as_mut());
+        set_oracle(deps.as_mut(), location, Decimal::percent(1000), 200, 86400);
+        invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap();
+
+        let pause = |investments, oracle, withdrawals| ExecuteMsg::SetPause {
+            investments,
+            oracle,
+            withdrawals,
+        };
+        let status = |deps: Deps| -> PauseStatusResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::PauseStatus {}).unwrap()).unwrap()
+        };
+        assert_eq!(status(deps.as_ref()), PauseStatusResponse::default());
+
+        // only the pauser or admin
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("investor", &[]),
+            pause(Some(true), None, None),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // pause investments
         let res = execute(
             deps.as_mut(),
-            env_at(35 * 86400),
+            mock_env(),
+            mock_info("pauser", &[]),
+            pause(Some(true), None, None),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "pause");
+        assert!(res.events[0]
+            .attributes
+            .contains(&attr("investments", "true")));
+        let err = invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap_err();
+        assert_eq!(err, ContractError::Paused("investments".to_string()));
+        // the oracle still works
+        set_oracle(deps.as_mut(), location, Decimal::percent(900), 300, 86400);
+
+        // admin pauses the rest, leaving investments as they are
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            pause(None, Some(true), Some(true)),
+        )
+        .unwrap();
+        assert_eq!(
+            status(deps.as_ref()),
+            PauseStatusResponse {
+                investments: true,
+                oracle: true,
+                withdrawals: true,
+            }
+        );
+        let oracle = ExecuteMsg::StoreOracle {
+            values: vec![OracleValues {
+                index: location.to_string(),
+                value: Decimal::percent(800),
+                time: time_at(400),
+            }],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env_at(86400),
+            mock_info("oracle", &[]),
+            oracle,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Paused("oracle".to_string()));
+        let withdraw = ExecuteMsg::Withdraw {
+            hexes: None,
+            ids: None,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env_at(40 * 86400),
+            mock_info("investor", &[]),
+            withdraw.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Paused("withdrawals".to_string()));
+
+        // resume withdrawals only
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            pause(None, None, Some(false)),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env_at(40 * 86400),
             mock_info("investor", &[]),
             withdraw,
         )
         .unwrap();
-        assert_eq!(res.messages, vec![]);
+        let err = invest_in(deps.as_mut(), "investor", location, 5000, 86400).unwrap_err();
+        assert_eq!(err, ContractError::Paused("investments".to_string()));
     }
 
     #[test]
@@ -810,12 +4701,161 @@ mod tests {
         let mut deps = mock_dependencies();
 
         let info = mock_info("creator", &[]);
-        let msg = init_with_locations(&["8765437FFFFFFFF", "1284639ffffffff"]);
+        let msg = init_with_locations(&["872830828FFFFFF", "822837fffffffff"]);
 
         // we can just call .unwrap() to assert this was a success
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        fund_reserves(deps.as_mut());
 
         // and ensure migrate passes
-        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap();
+    }
+
+    #[test]
+    fn migrate_between_versions() {
+        use crate::migration::{LegacyConfig, LegacyInvestment, LEGACY_CONFIG, LEGACY_INVESTMENTS};
+
+        let mut deps = mock_dependencies();
+        let location = "832830fffffffff";
+
+        // state as stored by v0.1.2, with one position that matured before the upgrade
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.1.2").unwrap();
+        let legacy = LegacyConfig {
+            oracle: Addr::unchecked("oracle"),
+            token: Addr::unchecked("token"),
+            max_investment_hex: Uint128::new(20_000_000),
+            maturity_days: 28,
+            measurement_window: 7,
+        };
+        LEGACY_CONFIG.save(&mut deps.storage, &legacy).unwrap();
+        let mut loc = Location::new();
+        loc.cur_index = Some(Measurement::new(
+            Decimal::percent(800),
+            time_at(29 * 86400 + 200),
+        ));
+        loc.total_invested = Uint128::new(5000);
+        loc.current_invested = Uint128::new(5000);
+        loc.total_investments = 1;
+        loc.current_investments = 1;
+        LOCATIONS.save(&mut deps.storage, location, &loc).unwrap();
+        let position = LegacyInvestment {
+            amount: Uint128::new(5000),
+            baseline_index: Decimal::percent(1000),
+            invested_time: time_at(86400),
+            maturity_time: time_at(29 * 86400),
+        };
+        LEGACY_INVESTMENTS
+            .save(
+                &mut deps.storage,
+                (&Addr::unchecked("investor"), location),
+                &vec![position],
+            )
+            .unwrap();
+
+        // config values are checked
+        let msg = MigrateMsg {
+            max_payout: Some(Decimal::percent(50)),
+            ..MigrateMsg::default()
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMaxPayout);
+        let msg = MigrateMsg {
+            resolutions: Some(ResolutionRange { min: 9, max: 7 }),
+            ..MigrateMsg::default()
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolutionRange);
+
+        let msg = MigrateMsg {
+            admin: Some("admin".to_string()),
+            max_payout: Some(Decimal::percent(150)),
+            oracle_guards: Some(OracleGuards {
+                max_deviation: Some(Decimal::percent(50)),
+                min_interval: None,
+            }),
+            resolutions: Some(ResolutionRange { min: 7, max: 9 }),
+            ..MigrateMsg::default()
+        };
+        let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let attrs = &res.events[0].attributes;
+        assert!(attrs.contains(&attr("from", "0.1.2")));
+        assert!(attrs.contains(&attr("to", CONTRACT_VERSION)));
+        assert!(attrs.contains(&attr("steps", "1")));
+        assert!(attrs.contains(&attr("investments", "1")));
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(cfg.admin, Some(Addr::unchecked("admin")));
+        assert_eq!(cfg.max_payout, Decimal::percent(150));
+        assert_eq!(cfg.oracle_guards.max_deviation, Some(Decimal::percent(50)));
+        assert_eq!(cfg.resolutions, ResolutionRange { min: 7, max: 9 });
+        assert_eq!(
+            get_contract_version(&deps.storage).unwrap().version,
+            CONTRACT_VERSION
+        );
+
+        // nothing was set aside for payouts before, so a gain cannot be paid yet
+        let solvency = query_solvency(deps.as_ref()).unwrap();
+        assert_eq!(solvency.tokens[0].reserve, Uint128::zero());
+        assert_eq!(solvency.tokens[0].invested, Uint128::new(5000));
+        assert!(!solvency.solvent);
+        assert_eq!(nft::query_num_tokens(deps.as_ref()).unwrap().count, 1);
+        let withdraw = |ids: Option<Vec<u64>>| ExecuteMsg::Withdraw {
+            hexes: None,
+            ids,
+            limit: None,
+            recipient: None,
+            msg: None,
+        };
+        // asking for it explicitly fails
+        let err = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw(Some(vec![1])),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve("token".to_string()));
+        // while withdrawing all defers it
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw(None),
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![]);
+        assert_eq!(res.events.len(), 1);
+        assert_eq!(res.events[0].ty, "withdraw-deferred");
+        assert!(res.events[0]
+            .attributes
+            .contains(&attr("investment_id", "1")));
+        let invest = query_investment(deps.as_ref(), env_at(30 * 86400), 1).unwrap();
+        assert_eq!(invest.investment.amount, Uint128::new(5000));
+
+        // once the reserve is funded, it settles against the index current at the upgrade
+        fund_reserves(deps.as_mut());
+        let res = execute(
+            deps.as_mut(),
+            env_at(30 * 86400),
+            mock_info("investor", &[]),
+            withdraw(None),
+        )
+        .unwrap();
+        assert_eq!(withdrawn_ids(&res), vec!["1".to_string()]);
+        assert!(res.events[0].attributes.contains(&attr("payout", "6250")));
+        assert_eq!(nft::query_num_tokens(deps.as_ref()).unwrap().count, 0);
+
+        // migrating again runs no steps
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap();
+        assert!(res.events[0].attributes.contains(&attr("steps", "0")));
+
+        // versions are compared by semver, not as strings
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.10.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMigration);
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.1.10").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap();
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "latest").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidVersion("latest".to_string()));
     }
 }
@@ -1,4 +1,5 @@
-use cosmwasm_std::{OverflowError, StdError};
+use cosmwasm_std::{Decimal, OverflowError, StdError, Uint128};
+use cw0::PaymentError;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -6,30 +7,143 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
+    #[error("Contract is paused: {0}")]
+    Paused(String),
+
     #[error("Invalid R3 Index: {0}")]
     InvalidR3(String),
 
-    #[error("Location not registered during initialization: {0}")]
+    #[error("Invalid R3 resolution: {0}")]
+    InvalidResolution(u8),
+
+    #[error("Min of the resolution range is above max, or max is above 15")]
+    InvalidResolutionRange,
+
+    #[error("Location {hex} is not between resolution {min} and {max}")]
+    ResolutionOutOfRange { hex: String, min: u8, max: u8 },
+
+    #[error("Location not registered: {0}")]
     UnregisteredLocation(String),
 
+    #[error("Location already registered: {0}")]
+    LocationExists(String),
+
+    #[error("Location is retired and accepts no new investments: {0}")]
+    LocationRetired(String),
+
+    #[error("Location is not retired: {0}")]
+    LocationNotRetired(String),
+
+    #[error("Must accept at least one token for payment")]
+    NoTokens,
+
     #[error("Cannot pay with token: {0}")]
     InvalidToken(String),
 
+    #[error("Address is already an oracle: {0}")]
+    DuplicateOracle(String),
+
+    #[error("Quorum must be between 1 and the number of oracles, got {0}")]
+    InvalidQuorum(u32),
+
+    #[error("No oracle handover pending")]
+    NoPendingOracle,
+
+    #[error("Not an oracle: {0}")]
+    NotOracle(String),
+
+    #[error("Invalid oracle public key")]
+    InvalidOracleKey,
+
+    #[error("No key registered for oracle: {0}")]
+    NoOracleKey(String),
+
+    #[error("Invalid oracle signature")]
+    InvalidSignature,
+
+    #[error("Key already registered by oracle: {0}")]
+    DuplicateOracleKey(String),
+
+    #[error("Nonce must be above the last one used: {0}")]
+    StaleNonce(u64),
+
     #[error("Oracle setting data from the future, unix time: {0}")]
     OracleFromTheFuture(u64),
 
+    #[error(
+        "Only {interval} seconds since the last measurement, at least {min_interval} required"
+    )]
+    UpdateTooSoon { interval: u64, min_interval: u64 },
+
+    #[error("Value {value} deviates too far from the last measurement {previous}")]
+    DeviationTooLarge { value: Decimal, previous: Decimal },
+
+    #[error("Value {0} is outside the allowed range of this location")]
+    ValueOutOfRange(Decimal),
+
+    #[error("Min of the value range is above max")]
+    InvalidValueRange,
+
     #[error("Cannot invest in a location without oracle data")]
     NoDataPresent,
 
     #[error("Last measurement was more than {days} days ago, cannot use")]
     DataTooOld { days: u64 },
 
+    #[error("Investment exceeds the maximum for this hex, only {remaining} can still be invested")]
+    ExceedsMaxInvestment { remaining: Uint128 },
+
+    #[error("Reserves of {0} cannot cover the worst-case payout of all investments")]
+    InsufficientReserve(String),
+
+    #[error("Max payout must be at least 1")]
+    InvalidMaxPayout,
+
+    #[error("Invalid payout curve: {0}")]
+    InvalidPayoutCurve(String),
+
+    #[error("No open investment with id {0}")]
+    InvestmentNotFound(u64),
+
+    #[error("Investment {0} is not mature or has no data to settle yet")]
+    CannotWithdraw(u64),
+
+    #[error("Investment {0} is requested more than once")]
+    DuplicateInvestment(u64),
+
+    #[error("Cannot deliver native token {0} with a message, only cw20 tokens support Send")]
+    CannotSendNative(String),
+
+    #[error("Not an accepted maturity tier: {0} days")]
+    InvalidMaturity(u64),
+
+    #[error("Early exit penalty must be at most 1")]
+    InvalidPenalty,
+
+    #[error("Early exits are not allowed")]
+    EarlyExitDisabled,
+
+    #[error("Investment {0} is mature, withdraw it instead")]
+    AlreadyMature(u64),
+
+    #[error("Invalid token id, must be an investment id: {0}")]
+    InvalidTokenId(String),
+
+    #[error("Approval has already expired")]
+    Expired,
+
     #[error("Cannot migrate from this contract")]
     InvalidMigration,
 
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+
     // TODO: remove when done
     #[error("Unimplemented")]
     Unimplemented,
@@ -1,6 +1,9 @@
 pub mod contract;
 mod error;
+mod migration;
 pub mod msg;
+pub mod nft;
+pub mod payout;
 pub mod r3;
 pub mod state;
 
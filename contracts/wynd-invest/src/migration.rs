@@ -0,0 +1,421 @@
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, Order, StdResult, Storage, Uint128};
+use cw20::Denom;
+use cw_storage_plus::{Item, Map};
+
+use crate::payout::{IndexDirection, PayoutCurve};
+use crate::state::{
+    count_investments, default_max_payout, default_quorum, denom_name, investments,
+    next_investment_id, Config, Investment, OracleGuards, Reserve, ResolutionRange, TierTotals,
+    TokenTotals, CONFIG, LOCATIONS, MEASUREMENTS, RESERVES,
+};
+
+/// Rewrites the state stored by earlier versions, returning the number of investments migrated
+pub type Upgrade = fn(&mut dyn Storage) -> StdResult<u64>;
+
+/// One change of the stored state, needed by all contracts migrating from before `version`
+pub struct UpgradeStep {
+    pub version: &'static str,
+    pub upgrade: Upgrade,
+}
+
+/// All upgrade steps, in the order they must run. Add one for every release that
+/// changes the stored state, and keep each idempotent
+pub const UPGRADES: &[UpgradeStep] = &[UpgradeStep {
+    version: "0.2.0",
+    upgrade: upgrade_v0_2,
+}];
+
+/// The steps needed to go from version `from` to `to`, in order
+pub fn upgrades_between(from: &Version, to: &Version) -> Vec<&'static UpgradeStep> {
+    UPGRADES
+        .iter()
+        .filter(|step| {
+            let version = Version::parse(step.version).expect("invalid upgrade step version");
+            from < &version && &version <= to
+        })
+        .collect()
+}
+
+/// v0.2.0 converts the single token config, locations and investments of v0.1.x,
+/// starts the measurement history and tracks the reserves
+fn upgrade_v0_2(storage: &mut dyn Storage) -> StdResult<u64> {
+    let migrated = migrate_legacy_state(storage)?;
+    migrate_measurements(storage)?;
+    migrate_reserves(storage)?;
+    Ok(migrated)
+}
+
+/// Config as stored up to v0.1.x, only supporting a cw20 token and without admin
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LegacyConfig {
+    pub oracle: Addr,
+    pub token: Addr,
+    pub max_investment_hex: Uint128,
+    pub maturity_days: u64,
+    pub measurement_window: u64,
+}
+
+pub const LEGACY_CONFIG: Item<LegacyConfig> = Item::new("config");
+
+/// Converts all state stored by v0.1.x into the current format, returning the number
+/// of investments migrated. A no-op if the config can already be read in the current format.
+pub fn migrate_legacy_state(storage: &mut dyn Storage) -> StdResult<u64> {
+    if CONFIG.load(storage).is_ok() {
+        return Ok(0);
+    }
+    let old = LEGACY_CONFIG.load(storage)?;
+    // everything was invested in the one cw20 token
+    let token = Denom::Cw20(old.token);
+    let cfg = Config {
+        admin: None,
+        pauser: None,
+        oracle: old.oracle,
+        oracles: vec![],
+        quorum: default_quorum(),
+        oracle_guards: OracleGuards::default(),
+        resolutions: ResolutionRange::default(),
+        tokens: vec![token.clone()],
+        max_investment_hex: old.max_investment_hex,
+        maturity_days: old.maturity_days,
+        maturity_tiers: vec![old.maturity_days],
+        measurement_window: old.measurement_window,
+        max_payout: default_max_payout(),
+        payout_curve: PayoutCurve::default(),
+        index_direction: IndexDirection::default(),
+        early_exit: None,
+    };
+    CONFIG.save(storage, &cfg)?;
+
+    migrate_legacy_locations(storage, &token, old.maturity_days)?;
+    migrate_legacy_investments(storage, &token)
+}
+
+/// Fills in the per token and per tier totals of all locations,
+/// which had all been invested in `token` for `maturity_days`
+pub fn migrate_legacy_locations(
+    storage: &mut dyn Storage,
+    token: &Denom,
+    maturity_days: u64,
+) -> StdResult<()> {
+    let locations: StdResult<Vec<_>> = LOCATIONS
+        .range_de(storage, None, None, Order::Ascending)
+        .collect();
+    for (hex, mut loc) in locations?.into_iter() {
+        if loc.tokens.is_empty() && !loc.total_invested.is_zero() {
+            loc.tokens.push(TokenTotals {
+                token: token.clone(),
+                total_invested: loc.total_invested,
+                current_invested: loc.current_invested,
+            });
+            loc.tiers.push(TierTotals {
+                maturity_days,
+                total_invested: loc.total_invested,
+                current_invested: loc.current_invested,
+                total_investments: loc.total_investments,
+                current_investments: loc.current_investments,
+            });
+            LOCATIONS.save(storage, &hex, &loc)?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts the measurement history of every location with its current index, which is all
+/// v0.1.x kept. Positions that matured before the upgrade settle against it, as they did before.
+pub fn migrate_measurements(storage: &mut dyn Storage) -> StdResult<()> {
+    let locations: StdResult<Vec<_>> = LOCATIONS
+        .range_de(storage, None, None, Order::Ascending)
+        .collect();
+    for (hex, loc) in locations?.into_iter() {
+        if let Some(cur) = loc.cur_index {
+            let key = (hex.as_str(), cur.time.into());
+            if !MEASUREMENTS.has(storage, key.clone()) {
+                MEASUREMENTS.save(storage, key, &cur.value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Builds the reserve ledger from the per token totals of all locations, if there is none yet.
+/// Nothing was set aside for payouts before, so all reserves start empty: migrated positions
+/// with a gain cannot be withdrawn until the reserve of their token is funded (they are deferred,
+/// or fail with `InsufficientReserve` if asked for by id), while those at a loss settle right away
+/// and add to the reserve.
+/// Fund the reserves along with the migration to avoid this.
+pub fn migrate_reserves(storage: &mut dyn Storage) -> StdResult<()> {
+    if RESERVES
+        .range(storage, None, None, Order::Ascending)
+        .next()
+        .is_some()
+    {
+        return Ok(());
+    }
+    let locations: StdResult<Vec<_>> = LOCATIONS
+        .range_de(storage, None, None, Order::Ascending)
+        .collect();
+    let mut reserves = Vec::<Reserve>::new();
+    for (_, loc) in locations?.into_iter() {
+        for totals in loc.tokens.into_iter() {
+            match reserves.iter_mut().find(|r| r.token == totals.token) {
+                Some(reserve) => reserve.invested += totals.current_invested,
+                None => {
+                    let mut reserve = Reserve::new(totals.token);
+                    reserve.invested = totals.current_invested;
+                    reserves.push(reserve);
+                }
+            }
+        }
+    }
+    for reserve in reserves.iter() {
+        RESERVES.save(storage, &denom_name(&reserve.token), reserve)?;
+    }
+    Ok(())
+}
+
+/// Investment as stored up to v0.1.x, without id, investor or hex
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LegacyInvestment {
+    pub amount: Uint128,
+    pub baseline_index: Decimal,
+    pub invested_time: u64,
+    pub maturity_time: u64,
+}
+
+/// Up to v0.1.x, all investments of one investor in one hex were stored in one vector
+pub const LEGACY_INVESTMENTS: Map<(&Addr, &str), Vec<LegacyInvestment>> = Map::new("investments");
+
+/// Moves all investments from the legacy vectors to the indexed map, assigning new ids.
+/// These had all been invested in `token`.
+/// Returns the number of investments migrated. A no-op if there is no legacy data.
+pub fn migrate_legacy_investments(storage: &mut dyn Storage, token: &Denom) -> StdResult<u64> {
+    let legacy: StdResult<Vec<_>> = LEGACY_INVESTMENTS
+        .range_de(storage, None, None, Order::Ascending)
+        .collect();
+
+    let mut count = 0;
+    for ((investor, hex), invests) in legacy?.into_iter() {
+        for old in invests.into_iter() {
+            let id = next_investment_id(storage)?;
+            let invest = Investment {
+                id,
+                investor: investor.clone(),
+                hex: hex.clone(),
+                token: token.clone(),
+                amount: old.amount,
+                baseline_index: old.baseline_index,
+                invested_time: old.invested_time,
+                maturity_time: old.maturity_time,
+                approvals: vec![],
+            };
+            investments().save(storage, id.into(), &invest)?;
+            count += 1;
+        }
+        LEGACY_INVESTMENTS.remove(storage, (&investor, &hex));
+    }
+    count_investments(storage, count, 0)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::msg::MigrateMsg;
+    use crate::state::{measurement_after, Location, Measurement};
+    use cosmwasm_std::from_slice;
+    use cosmwasm_std::testing::mock_dependencies;
+
+    fn legacy(amount: u128, maturity_time: u64) -> LegacyInvestment {
+        LegacyInvestment {
+            amount: Uint128::new(amount),
+            baseline_index: Decimal::percent(150),
+            invested_time: 1000,
+            maturity_time,
+        }
+    }
+
+    #[test]
+    fn upgrade_steps_in_order() {
+        let v = |s| Version::parse(s).unwrap();
+        let mut last = v("0.0.0");
+        for step in UPGRADES {
+            let version = v(step.version);
+            assert!(version > last);
+            last = version;
+        }
+
+        assert_eq!(upgrades_between(&v("0.1.2"), &v("0.2.0")).len(), 1);
+        assert_eq!(upgrades_between(&v("0.1.10"), &v("0.2.0")).len(), 1);
+        assert_eq!(upgrades_between(&v("0.2.0"), &v("0.2.1")).len(), 0);
+        assert_eq!(upgrades_between(&v("0.1.0"), &v("0.1.9")).len(), 0);
+    }
+
+    #[test]
+    fn migrate_msg_fields_are_optional() {
+        let msg: MigrateMsg = from_slice(b"{}").unwrap();
+        assert_eq!(msg, MigrateMsg::default());
+    }
+
+    #[test]
+    fn migrates_legacy_state() {
+        let mut deps = mock_dependencies();
+        let old = LegacyConfig {
+            oracle: Addr::unchecked("oracle"),
+            token: Addr::unchecked("token"),
+            max_investment_hex: Uint128::new(5000),
+            maturity_days: 30,
+            measurement_window: 7,
+        };
+        LEGACY_CONFIG.save(&mut deps.storage, &old).unwrap();
+        CONFIG.load(&deps.storage).unwrap_err();
+
+        // one location with investments, one without
+        let hex = "832830fffffffff";
+        let mut loc = Location::new();
+        loc.total_invested = Uint128::new(300);
+        loc.current_invested = Uint128::new(100);
+        loc.total_investments = 2;
+        loc.current_investments = 1;
+        LOCATIONS.save(&mut deps.storage, hex, &loc).unwrap();
+        LOCATIONS
+            .save(&mut deps.storage, "8928308280fffff", &Location::new())
+            .unwrap();
+        let alice = Addr::unchecked("alice");
+        LEGACY_INVESTMENTS
+            .save(&mut deps.storage, (&alice, hex), &vec![legacy(100, 5000)])
+            .unwrap();
+
+        let count = migrate_legacy_state(&mut deps.storage).unwrap();
+        assert_eq!(count, 1);
+        let token = Denom::Cw20(Addr::unchecked("token"));
+        let cfg = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(cfg.admin, None);
+        assert_eq!(cfg.tokens, vec![token.clone()]);
+        assert_eq!(cfg.max_investment_hex, Uint128::new(5000));
+
+        let loc = LOCATIONS.load(&deps.storage, hex).unwrap();
+        assert_eq!(
+            loc.tokens,
+            vec![TokenTotals {
+                token: token.clone(),
+                total_invested: Uint128::new(300),
+                current_invested: Uint128::new(100),
+            }]
+        );
+        assert_eq!(loc.tiers.len(), 1);
+        assert_eq!(loc.tiers[0].maturity_days, 30);
+        assert_eq!(loc.tiers[0].current_investments, 1);
+        let empty = LOCATIONS.load(&deps.storage, "8928308280fffff").unwrap();
+        assert_eq!(empty.tokens, vec![]);
+
+        let invest = investments().load(&deps.storage, 1.into()).unwrap();
+        assert_eq!(invest.token, token);
+
+        // ledger tracks what is still invested, with nothing reserved
+        migrate_reserves(&mut deps.storage).unwrap();
+        let reserve = Reserve::load(&deps.storage, &token).unwrap();
+        assert_eq!(reserve.invested, Uint128::new(100));
+        assert_eq!(reserve.reserve, Uint128::zero());
+
+        // second time leaves it alone
+        assert_eq!(migrate_legacy_state(&mut deps.storage).unwrap(), 0);
+        assert_eq!(CONFIG.load(&deps.storage).unwrap(), cfg);
+    }
+
+    #[test]
+    fn migrates_measurements() {
+        let mut deps = mock_dependencies();
+        let hex = "832830fffffffff";
+        let mut loc = Location::new();
+        loc.cur_index = Some(Measurement::new(Decimal::percent(120), 5000));
+        LOCATIONS.save(&mut deps.storage, hex, &loc).unwrap();
+        LOCATIONS
+            .save(&mut deps.storage, "8928308280fffff", &Location::new())
+            .unwrap();
+
+        // the current index starts the history, locations without one have none
+        migrate_measurements(&mut deps.storage).unwrap();
+        migrate_measurements(&mut deps.storage).unwrap();
+        let history: Vec<_> = MEASUREMENTS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(
+            measurement_after(&deps.storage, hex, 4000).unwrap(),
+            Some(Measurement::new(Decimal::percent(120), 5000))
+        );
+    }
+
+    #[test]
+    fn migrates_legacy_investments() {
+        let mut deps = mock_dependencies();
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        let hex = "832830fffffffff";
+        let hex2 = "8928308280fffff";
+
+        LEGACY_INVESTMENTS
+            .save(
+                &mut deps.storage,
+                (&alice, hex),
+                &vec![legacy(100, 5000), legacy(200, 6000)],
+            )
+            .unwrap();
+        LEGACY_INVESTMENTS
+            .save(&mut deps.storage, (&alice, hex2), &vec![legacy(300, 7000)])
+            .unwrap();
+        LEGACY_INVESTMENTS
+            .save(&mut deps.storage, (&bob, hex), &vec![legacy(400, 8000)])
+            .unwrap();
+
+        let token = Denom::Native("ustable".to_string());
+        let count = migrate_legacy_investments(&mut deps.storage, &token).unwrap();
+        assert_eq!(count, 4);
+
+        // legacy data is gone, running again is a no-op
+        let left = LEGACY_INVESTMENTS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .count();
+        assert_eq!(left, 0);
+        assert_eq!(
+            migrate_legacy_investments(&mut deps.storage, &token).unwrap(),
+            0
+        );
+
+        // all available by id and index (legacy keys sort by address length first)
+        let alices: StdResult<Vec<_>> = investments()
+            .idx
+            .investor
+            .prefix(alice.clone())
+            .range(&deps.storage, None, None, Order::Ascending)
+            .map(|r| r.map(|(_, inv)| inv))
+            .collect();
+        let alices = alices.unwrap();
+        assert_eq!(alices.len(), 3);
+        assert_eq!(alices[0].id, 2);
+        assert_eq!(alices[0].hex, hex);
+        assert_eq!(alices[1].amount, Uint128::new(200));
+        assert_eq!(alices[2].hex, hex2);
+
+        let bobs = investments().load(&deps.storage, 1.into()).unwrap();
+        assert_eq!(bobs.investor, bob);
+        assert_eq!(bobs.amount, Uint128::new(400));
+        assert_eq!(bobs.maturity_time, 8000);
+
+        // maturity index orders by maturity, not id
+        let first: Vec<_> = investments()
+            .idx
+            .maturity
+            .range(&deps.storage, None, None, Order::Ascending)
+            .map(|r| r.unwrap().1.id)
+            .collect();
+        assert_eq!(first, vec![2, 3, 4, 1]);
+
+        // new ids continue after the migrated ones
+        assert_eq!(next_investment_id(&mut deps.storage).unwrap(), 5);
+    }
+}
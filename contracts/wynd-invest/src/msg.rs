@@ -1,43 +1,220 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::state::{Config, Investment, Location, Measurement};
-use cosmwasm_std::{Decimal, Env, Uint128};
-use cw20::Cw20ReceiveMsg;
+use crate::payout::{IndexDirection, PayoutCurve};
+use crate::state::{
+    measurement_after, Approval, Config, Investment, Location, Measurement, OracleGuards,
+    OracleKey, PauseFlags, PendingOracle, Reserve, ResolutionRange, TierTotals, TokenTotals,
+    ValueRange,
+};
+use crate::ContractError;
+use cosmwasm_std::{
+    to_binary, to_vec, Addr, Binary, CosmosMsg, Decimal, Env, StdResult, Storage, Uint128, WasmMsg,
+};
+use cw0::Expiration;
+use cw20::{Cw20ReceiveMsg, Denom};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
+    // address that can manage locations after instantiation, none if they are fixed
+    pub admin: Option<String>,
+    // address that can pause and unpause parts of the contract in an emergency (as can the admin)
+    #[serde(default)]
+    pub pauser: Option<String>,
     // address of oracle contract (this allows writing data)
     pub oracle: String,
+    // further oracles, each submitting values for the same hexes and times
+    #[serde(default)]
+    pub oracles: Vec<String>,
+    // how many oracles must submit a value for a hex and time before the median is stored (default 1)
+    #[serde(default)]
+    pub quorum: Option<u32>,
+    // keys of oracles that sign their values for anyone to relay
+    #[serde(default)]
+    pub oracle_keys: Vec<RegisteredKey>,
+    // limits on how oracle values may change, to reject outliers
+    #[serde(default)]
+    pub oracle_guards: OracleGuards,
     // list of all r3 locations that can be invested (as hex)
     pub locations: Vec<String>,
-    // address of the cw20 token that we use for payment
-    pub token: String,
+    // the resolutions of r3 locations that may be added (default: all)
+    #[serde(default)]
+    pub resolutions: ResolutionRange,
+    // the native denoms or cw20 token addresses that we accept for payment.
+    // these should be of equal value (eg. stablecoins), as max_investment_hex applies to the sum
+    pub tokens: Vec<Denom>,
     // maximum amount that can be invested in one hex
     pub max_investment_hex: Uint128,
-    // how many days the investment takes until maturity (eg. we pay out in the results in 30 days, 180 days)
+    // how many days the investment takes until maturity (eg. we pay out in the results in 30 days, 180 days),
+    // unless the investor picks another tier
     pub maturity_days: u64,
+    // other maturities (in days) investors can pick from
+    #[serde(default)]
+    pub maturity_tiers: Vec<u64>,
     // how many days margin we have from measurement to usage.
     // when investing, the latest data must be within X days
     // when investment finishes, there must be data within X days of maturity
     pub measurement_window: u64,
+    // the most an investment can pay out, as a multiple of the amount invested (at least 1)
+    pub max_payout: Decimal,
+    // how the payout follows the index, always capped by max_payout
+    pub payout_curve: PayoutCurve,
+    // whether a falling or a rising index is good for the investors
+    pub index_direction: IndexDirection,
+    // share of the amount kept when exiting right after investing (at most 1),
+    // decaying linearly to zero at maturity. early exits are not allowed if unset
+    #[serde(default)]
+    pub early_exit_penalty: Option<Decimal>,
+    // receives the early exit penalties, if unset they are added to the reserve
+    #[serde(default)]
+    pub fee_collector: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct MigrateMsg {}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct MigrateMsg {
+    // set a new admin, which can manage the locations
+    #[serde(default)]
+    pub admin: Option<String>,
+    // the config values below are left as they are if unset
+    #[serde(default)]
+    pub pauser: Option<String>,
+    #[serde(default)]
+    pub max_investment_hex: Option<Uint128>,
+    #[serde(default)]
+    pub measurement_window: Option<u64>,
+    #[serde(default)]
+    pub max_payout: Option<Decimal>,
+    #[serde(default)]
+    pub quorum: Option<u32>,
+    #[serde(default)]
+    pub oracle_guards: Option<OracleGuards>,
+    // only applies to locations added later on
+    #[serde(default)]
+    pub resolutions: Option<ResolutionRange>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
+    /// Invest cw20 tokens, with a ReceiveMsg as payload
     Receive(Cw20ReceiveMsg),
-    /// This will return funds from all finished investments
-    Withdraw {},
+    /// Invest the native tokens sent along in the given hex.
+    /// Only valid if the denom is one of the accepted tokens.
+    /// Matures after maturity_days, which must be one of the tiers (default: config.maturity_days)
+    Invest {
+        hex: String,
+        maturity_days: Option<u64>,
+    },
+    /// Add the native tokens sent along to the reserve backing payouts.
+    /// Only valid if the denom is one of the accepted tokens
+    FundReserve {},
+    /// This will return funds from finished investments.
+    /// By default, all that can be withdrawn, otherwise only those in the given hexes
+    /// or with the given ids (which must all be withdrawable).
+    /// At most limit investments are withdrawn, if set.
+    /// Without ids, gains the reserve cannot pay yet are deferred (and reported) rather than failing.
+    /// The payout goes to recipient (default: sender). If msg is set, cw20 payouts
+    /// are delivered with Send, calling the recipient contract with this msg.
+    Withdraw {
+        hexes: Option<Vec<String>>,
+        ids: Option<Vec<u64>>,
+        limit: Option<u32>,
+        recipient: Option<String>,
+        msg: Option<Binary>,
+    },
+    /// Returns the amount of an immature investment, minus a penalty decaying until maturity.
+    /// Only if early exits are enabled in the config
+    ExitEarly {
+        id: u64,
+    },
     StoreOracle {
         values: Vec<OracleValues>,
     },
-    /// The current oracle can designate a new oracle
-    UpdateOracle {
+    /// Anyone can relay values signed by the registered key of an oracle.
+    /// The signature is over `signed_payload` of this contract, the oracle, the nonce and the values.
+    /// The nonce must be above the last one relayed for the oracle
+    StoreSignedOracle {
+        oracle: String,
+        nonce: u64,
+        values: Vec<OracleValues>,
+        signature: Binary,
+    },
+    /// Any oracle can propose a new address to take its place, which must accept
+    /// before it expires (default: one week). Replaces an earlier proposal
+    ProposeOracle {
+        oracle: String,
+        expires: Option<Expiration>,
+    },
+    /// The proposed address takes the place of the oracle that proposed it.
+    /// The registered key of the old one is removed
+    AcceptOracle {},
+    /// The oracle withdraws its proposal
+    CancelOracle {},
+    /// The oracle itself can register the key it signs values with, None removes it.
+    /// The admin can only remove a key
+    SetOracleKey {
         oracle: String,
+        key: Option<OracleKey>,
+    },
+    /// The admin can register new locations to invest in
+    AddLocations {
+        locations: Vec<String>,
+    },
+    /// The admin can stop new investments in a location. Existing ones still settle
+    RetireLocation {
+        hex: String,
+    },
+    /// The admin can allow investing in a retired location again
+    ReopenLocation {
+        hex: String,
+    },
+    /// The admin can change the limits on how oracle values may change
+    SetOracleGuards {
+        guards: OracleGuards,
+    },
+    /// The admin can limit the oracle values accepted for a location, None removes the limit
+    SetValueRange {
+        hex: String,
+        range: Option<ValueRange>,
+    },
+    /// The pauser or admin can halt (true) or resume (false) parts of the contract.
+    /// Flags that are not set stay as they are
+    SetPause {
+        investments: Option<bool>,
+        oracle: Option<bool>,
+        withdrawals: Option<bool>,
+    },
+    /// cw721: transfer an investment (token_id is the investment id) to a new owner,
+    /// who can withdraw it from then on
+    TransferNft {
+        recipient: String,
+        token_id: String,
+    },
+    /// cw721: transfer an investment to a contract and call it with ReceiveNft
+    SendNft {
+        contract: String,
+        token_id: String,
+        msg: Binary,
+    },
+    /// cw721: allow spender to transfer this investment
+    Approve {
+        spender: String,
+        token_id: String,
+        expires: Option<Expiration>,
+    },
+    /// cw721: remove an approval for this investment
+    Revoke {
+        spender: String,
+        token_id: String,
+    },
+    /// cw721: allow operator to transfer all investments of the sender
+    ApproveAll {
+        operator: String,
+        expires: Option<Expiration>,
+    },
+    /// cw721: remove an operator
+    RevokeAll {
+        operator: String,
     },
 }
 
@@ -51,11 +228,49 @@ pub struct OracleValues {
     pub time: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RegisteredKey {
+    pub oracle: String,
+    pub key: OracleKey,
+}
+
+/// What an oracle signs for `ExecuteMsg::StoreSignedOracle`.
+/// Includes the contract and the oracle, so the signature cannot be replayed on another
+/// contract or for another oracle, and the nonce so it cannot be replayed at all
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SignedPayload {
+    pub contract: String,
+    pub oracle: String,
+    pub nonce: u64,
+    pub values: Vec<OracleValues>,
+}
+
+/// The bytes to sign: json of `SignedPayload`
+pub fn signed_payload(
+    contract: &Addr,
+    oracle: &Addr,
+    nonce: u64,
+    values: &[OracleValues],
+) -> StdResult<Vec<u8>> {
+    to_vec(&SignedPayload {
+        contract: contract.to_string(),
+        oracle: oracle.to_string(),
+        nonce,
+        values: values.to_vec(),
+    })
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ReceiveMsg {
-    // returns investment_id in event and
-    Invest { hex: String },
+    // returns investment_id in event.
+    // matures after maturity_days, which must be one of the tiers (default: config.maturity_days)
+    Invest {
+        hex: String,
+        maturity_days: Option<u64>,
+    },
+    // adds the tokens to the reserve backing payouts
+    FundReserve {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -66,25 +281,232 @@ pub enum QueryMsg {
     Info {
         hex: String,
     },
-    // List all investments by user, possibly filtering on one hex location
-    // FIXME: add pagination?
+    // How much more can be invested in one hex before it is full
+    Capacity {
+        hex: String,
+    },
+    // List all investments by user, possibly filtering on one hex location.
+    // Paginated by investment id, start_after is the last id returned
     ListInvestments {
         investor: String,
         hex: Option<String>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    // Details of one open investment by id
+    Investment {
+        id: u64,
+    },
+    // Totals over the open investments of one investor, by investment id.
+    // If last_id is returned, query again with it as start_after and add up the pages
+    Portfolio {
+        investor: String,
+        #[serde(default)]
+        start_after: Option<u64>,
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+    // Runs all checks of investing now, without investing, and returns what would be locked in.
+    // Token and maturity default as in ReceiveMsg::Invest, with the first accepted token
+    SimulateInvest {
+        hex: String,
+        amount: Uint128,
+        #[serde(default)]
+        token: Option<Denom>,
+        #[serde(default)]
+        maturity_days: Option<u64>,
+    },
+    // What a Withdraw without filters by this investor would pay out now
+    SimulateWithdraw {
+        investor: String,
+    },
+    // List all registered locations, with their current index and investment totals
+    ListLocations {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Reserves and worst-case liabilities for each accepted token
+    Solvency {},
+    // Which parts of the contract are paused
+    PauseStatus {},
+    // The key an oracle signs relayed values with, if any
+    OracleKey {
+        oracle: String,
+    },
+    // The handover proposed by this oracle, if any
+    PendingOracle {
+        oracle: String,
+    },
+    // cw721: owner of an investment (token_id is the investment id), and who may transfer it
+    OwnerOf {
+        token_id: String,
+        include_expired: Option<bool>,
+    },
+    // cw721: operators that may transfer all investments of the owner
+    AllOperators {
+        owner: String,
+        include_expired: Option<bool>,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // cw721: number of open investments
+    NumTokens {},
+    // cw721: name and symbol of the investment tokens
+    ContractInfo {},
+    // cw721: metadata of an investment
+    NftInfo {
+        token_id: String,
+    },
+    // cw721: OwnerOf and NftInfo in one
+    AllNftInfo {
+        token_id: String,
+        include_expired: Option<bool>,
     },
+    // cw721: ids of all investments owned by owner
+    Tokens {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // cw721: ids of all open investments
+    AllTokens {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OwnerOfResponse {
+    pub owner: Addr,
+    pub approvals: Vec<Approval>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OperatorsResponse {
+    pub operators: Vec<Approval>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NumTokensResponse {
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractInfoResponse {
+    pub name: String,
+    pub symbol: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NftInfoResponse {
+    // always None, all metadata is on chain
+    pub token_uri: Option<String>,
+    pub extension: InvestmentMetadata,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InvestmentMetadata {
+    // r3 index of the location invested in
+    pub hex: String,
+    // which token was invested, and will be paid out
+    pub token: Denom,
+    // how much was invested
+    pub amount: Uint128,
+    // starting value when investment was created
+    pub baseline_index: Decimal,
+    // when this investment was made - in UNIX seconds UTC
+    pub invested: u64,
+    // when this investment can be claimed - in UNIX seconds UTC
+    pub maturity_date: u64,
+}
+
+impl From<Investment> for NftInfoResponse {
+    fn from(invest: Investment) -> Self {
+        NftInfoResponse {
+            token_uri: None,
+            extension: InvestmentMetadata {
+                hex: invest.hex,
+                token: invest.token,
+                amount: invest.amount,
+                baseline_index: invest.baseline_index,
+                invested: invest.invested_time,
+                maturity_date: invest.maturity_time,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllNftInfoResponse {
+    pub access: OwnerOfResponse,
+    pub info: NftInfoResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokensResponse {
+    // investment ids as strings, in ascending order
+    pub tokens: Vec<String>,
+}
+
+/// Sent to the receiving contract by SendNft, as in cw721
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Cw721ReceiveMsg {
+    pub sender: String,
+    pub token_id: String,
+    pub msg: Binary,
+}
+
+impl Cw721ReceiveMsg {
+    pub fn into_cosmos_msg(self, contract: String) -> StdResult<CosmosMsg> {
+        let msg = to_binary(&ReceiverExecuteMsg::ReceiveNft(self))?;
+        Ok(WasmMsg::Execute {
+            contract_addr: contract,
+            msg,
+            funds: vec![],
+        }
+        .into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum ReceiverExecuteMsg {
+    ReceiveNft(Cw721ReceiveMsg),
 }
 
 pub type ConfigResponse = Config;
 
+pub type PauseStatusResponse = PauseFlags;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingOracleResponse {
+    pub pending: Option<PendingOracle>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OracleKeyResponse {
+    pub key: Option<OracleKey>,
+    // the next signed values must use a higher nonce
+    pub last_nonce: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
 pub struct InfoResponse {
     pub cur_index: Option<Measurement>,
-    // amount of money invested here
+    // true if this no longer accepts new investments
+    pub retired: bool,
+    // amount of money invested here, summed over all tokens
     pub total_invested: Uint128,
     pub current_invested: Uint128,
     // number of individual investments made (people)
     pub total_investments: u64,
     pub current_investments: u64,
+    // amount of money invested here in each token
+    pub tokens: Vec<TokenTotals>,
+    // amount of money invested here in each maturity tier
+    pub tiers: Vec<TierTotals>,
+    // oracle values outside of this range are rejected (if set)
+    pub value_range: Option<ValueRange>,
 }
 
 impl InfoResponse {
@@ -93,6 +515,172 @@ impl InfoResponse {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CapacityResponse {
+    // maximum amount that can be invested in one hex
+    pub max_investment: Uint128,
+    // amount currently invested here
+    pub current_invested: Uint128,
+    // how much can still be invested, zero when the hex is full
+    pub remaining: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PortfolioResponse {
+    // number of open investments
+    pub investments: u64,
+    // totals in each token, as these are paid out separately
+    pub tokens: Vec<PortfolioTotals>,
+    // investments that are mature, but not withdrawn yet
+    pub mature: u64,
+    // the earliest maturity of the investments that are not mature yet - in UNIX seconds UTC
+    pub next_maturity: Option<u64>,
+    // set if the limit was reached, more investments may follow this id
+    pub last_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PortfolioTotals {
+    pub token: Denom,
+    // how much was invested
+    pub principal: Uint128,
+    // how much can be withdrawn now
+    pub claimable: Uint128,
+    // what all would pay out, using the current index for those not settled yet
+    pub projected: Uint128,
+}
+
+impl PortfolioTotals {
+    pub fn new(token: Denom) -> Self {
+        PortfolioTotals {
+            token,
+            principal: Uint128::zero(),
+            claimable: Uint128::zero(),
+            projected: Uint128::zero(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateInvestResponse {
+    pub hex: String,
+    // latest measurement, which becomes the baseline
+    pub baseline_index: Measurement,
+    pub maturity_days: u64,
+    // when this investment could be claimed - in UNIX seconds UTC
+    pub maturity_date: u64,
+    // the most this investment could pay out
+    pub max_payout: Uint128,
+    // how much can still be invested in this hex afterwards
+    pub remaining_capacity: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateWithdrawResponse {
+    pub investments: Vec<SimulatedWithdrawal>,
+    // mature ones with a gain the reserves cannot pay yet, which stay open
+    pub deferred: Vec<SimulatedWithdrawal>,
+    // how much would be paid out in each token
+    pub totals: Vec<TokenAmount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulatedWithdrawal {
+    pub id: u64,
+    pub hex: String,
+    pub token: Denom,
+    // how much was invested
+    pub amount: Uint128,
+    pub payout: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenAmount {
+    pub token: Denom,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SolvencyResponse {
+    // true iff the reserves of every token cover their liabilities
+    pub solvent: bool,
+    pub tokens: Vec<TokenSolvency>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenSolvency {
+    pub token: Denom,
+    // funds set aside to pay out rewards above the amount invested
+    pub reserve: Uint128,
+    // amount of all open investments in this token
+    pub invested: Uint128,
+    // worst-case payout above the amount invested, if every investment paid the max
+    pub liabilities: Uint128,
+    // true iff the reserve covers the liabilities
+    pub solvent: bool,
+}
+
+impl TokenSolvency {
+    pub fn new(reserve: Reserve, cfg: &Config) -> Self {
+        TokenSolvency {
+            liabilities: reserve.liabilities(cfg),
+            solvent: reserve.is_solvent(cfg),
+            token: reserve.token,
+            reserve: reserve.reserve,
+            invested: reserve.invested,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListLocationsResponse {
+    pub locations: Vec<LocationResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LocationResponse {
+    pub hex: String,
+    pub cur_index: Option<Measurement>,
+    // true if this no longer accepts new investments
+    pub retired: bool,
+    // amount of money invested here, summed over all tokens
+    pub total_invested: Uint128,
+    pub current_invested: Uint128,
+    // number of individual investments made (people)
+    pub total_investments: u64,
+    pub current_investments: u64,
+    // amount of money invested here in each token
+    pub tokens: Vec<TokenTotals>,
+    // amount of money invested here in each maturity tier
+    pub tiers: Vec<TierTotals>,
+    // oracle values outside of this range are rejected (if set)
+    pub value_range: Option<ValueRange>,
+}
+
+impl LocationResponse {
+    pub fn new(hex: String, loc: Location) -> Self {
+        LocationResponse {
+            hex,
+            cur_index: loc.cur_index,
+            retired: loc.retired,
+            total_invested: loc.total_invested,
+            current_invested: loc.current_invested,
+            total_investments: loc.total_investments,
+            current_investments: loc.current_investments,
+            tokens: loc.tokens,
+            tiers: loc.tiers,
+            value_range: loc.value_range,
+        }
+    }
+}
+
+// When querying by id, the investor is not implied, so we add it here
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InvestmentInfoResponse {
+    pub investor: Addr,
+    pub investment: InvestmentResponse,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ListInvestmentsResponse {
     pub investments: Vec<InvestmentResponse>,
@@ -101,7 +689,10 @@ pub struct ListInvestmentsResponse {
 // Note: we do not include address here. It is verbose and implied in the query
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InvestmentResponse {
+    pub id: u64,
     pub hex: String,
+    // which token was invested, and will be paid out
+    pub token: Denom,
     // how much was invested
     pub amount: Uint128,
     // starting value when investment was created
@@ -119,13 +710,22 @@ pub struct InvestmentResponse {
 }
 
 impl InvestmentResponse {
-    pub fn new(invest: Investment, hex: &str, cfg: &Config, loc: &Location, env: &Env) -> Self {
-        let (withdraw_amount, can_withdraw) = match invest.reward(env, loc, cfg) {
+    pub fn new(
+        storage: &dyn Storage,
+        invest: Investment,
+        cfg: &Config,
+        loc: &Location,
+        env: &Env,
+    ) -> Result<Self, ContractError> {
+        let settlement = measurement_after(storage, &invest.hex, invest.maturity_time)?;
+        let (withdraw_amount, can_withdraw) = match invest.reward(env, settlement, cfg)? {
             Some(reward) => (reward, true),
-            None => (invest.would_reward(loc), false),
+            None => (invest.would_reward(loc, cfg)?, false),
         };
-        InvestmentResponse {
-            hex: hex.into(),
+        Ok(InvestmentResponse {
+            id: invest.id,
+            hex: invest.hex,
+            token: invest.token,
             amount: invest.amount,
             baseline_index: invest.baseline_index,
             // this will always be Some, as we never allow investing if it was None
@@ -134,6 +734,6 @@ impl InvestmentResponse {
             can_withdraw,
             invested: invest.invested_time,
             maturity_date: invest.maturity_time,
-        }
+        })
     }
 }
@@ -0,0 +1,343 @@
+//! Every investment is also a cw721 token, with the investment id as token id.
+//! Transferring it moves the right to withdraw (or exit early) to the new owner.
+
+use cosmwasm_std::{
+    Addr, Binary, BlockInfo, Deps, DepsMut, Env, Event, MessageInfo, Order, Response, StdResult,
+};
+use cw0::Expiration;
+use cw_storage_plus::Bound;
+
+use crate::error::ContractError;
+use crate::msg::{
+    AllNftInfoResponse, ContractInfoResponse, Cw721ReceiveMsg, NftInfoResponse, NumTokensResponse,
+    OperatorsResponse, OwnerOfResponse, TokensResponse,
+};
+use crate::state::{investments, Approval, Investment, OPEN_INVESTMENTS, OPERATORS};
+
+pub const NFT_NAME: &str = "WYND Impact Investment";
+pub const NFT_SYMBOL: &str = "WYNDI";
+
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn parse_token_id(token_id: &str) -> Result<u64, ContractError> {
+    token_id
+        .parse()
+        .map_err(|_| ContractError::InvalidTokenId(token_id.to_string()))
+}
+
+fn load_token(deps: Deps, token_id: &str) -> Result<Investment, ContractError> {
+    let id = parse_token_id(token_id)?;
+    investments()
+        .may_load(deps.storage, id.into())?
+        .ok_or(ContractError::InvestmentNotFound(id))
+}
+
+fn is_operator(deps: Deps, block: &BlockInfo, owner: &Addr, sender: &Addr) -> StdResult<bool> {
+    Ok(OPERATORS
+        .may_load(deps.storage, (owner, sender))?
+        .map(|expires| !expires.is_expired(block))
+        .unwrap_or(false))
+}
+
+/// the owner, an approved spender or an operator of the owner may transfer
+fn check_can_send(
+    deps: Deps,
+    env: &Env,
+    sender: &Addr,
+    invest: &Investment,
+) -> Result<(), ContractError> {
+    if &invest.investor == sender
+        || invest
+            .approvals
+            .iter()
+            .any(|a| &a.spender == sender && !a.expires.is_expired(&env.block))
+        || is_operator(deps, &env.block, &invest.investor, sender)?
+    {
+        Ok(())
+    } else {
+        Err(ContractError::Unauthorized {})
+    }
+}
+
+/// the owner or an operator of the owner may approve spenders
+fn check_can_approve(
+    deps: Deps,
+    env: &Env,
+    sender: &Addr,
+    invest: &Investment,
+) -> Result<(), ContractError> {
+    if &invest.investor == sender || is_operator(deps, &env.block, &invest.investor, sender)? {
+        Ok(())
+    } else {
+        Err(ContractError::Unauthorized {})
+    }
+}
+
+fn transfer(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    recipient: Addr,
+    token_id: &str,
+) -> Result<Investment, ContractError> {
+    let mut invest = load_token(deps.as_ref(), token_id)?;
+    check_can_send(deps.as_ref(), env, sender, &invest)?;
+    invest.investor = recipient;
+    invest.approvals = vec![];
+    investments().save(deps.storage, invest.id.into(), &invest)?;
+    Ok(invest)
+}
+
+pub fn transfer_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let recipient = deps.api.addr_validate(&recipient)?;
+    transfer(deps, &env, &info.sender, recipient.clone(), &token_id)?;
+
+    let evt = Event::new("transfer-nft")
+        .add_attribute("sender", info.sender)
+        .add_attribute("recipient", recipient)
+        .add_attribute("token_id", token_id);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn send_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    contract: String,
+    token_id: String,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    let contract = deps.api.addr_validate(&contract)?;
+    transfer(deps, &env, &info.sender, contract.clone(), &token_id)?;
+
+    let hook = Cw721ReceiveMsg {
+        sender: info.sender.to_string(),
+        token_id: token_id.clone(),
+        msg,
+    }
+    .into_cosmos_msg(contract.to_string())?;
+    let evt = Event::new("send-nft")
+        .add_attribute("sender", info.sender)
+        .add_attribute("recipient", contract)
+        .add_attribute("token_id", token_id);
+    Ok(Response::new().add_event(evt).add_message(hook))
+}
+
+pub fn approve(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    token_id: String,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+    let mut invest = load_token(deps.as_ref(), &token_id)?;
+    check_can_approve(deps.as_ref(), &env, &info.sender, &invest)?;
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired);
+    }
+    invest.approvals.retain(|a| a.spender != spender);
+    invest.approvals.push(Approval {
+        spender: spender.clone(),
+        expires,
+    });
+    investments().save(deps.storage, invest.id.into(), &invest)?;
+
+    let evt = Event::new("approve")
+        .add_attribute("sender", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("token_id", token_id);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn revoke(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+    let mut invest = load_token(deps.as_ref(), &token_id)?;
+    check_can_approve(deps.as_ref(), &env, &info.sender, &invest)?;
+    invest.approvals.retain(|a| a.spender != spender);
+    investments().save(deps.storage, invest.id.into(), &invest)?;
+
+    let evt = Event::new("revoke")
+        .add_attribute("sender", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("token_id", token_id);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn approve_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    operator: String,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let operator = deps.api.addr_validate(&operator)?;
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired);
+    }
+    OPERATORS.save(deps.storage, (&info.sender, &operator), &expires)?;
+
+    let evt = Event::new("approve-all")
+        .add_attribute("sender", info.sender)
+        .add_attribute("operator", operator);
+    Ok(Response::new().add_event(evt))
+}
+
+pub fn revoke_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    operator: String,
+) -> Result<Response, ContractError> {
+    let operator = deps.api.addr_validate(&operator)?;
+    OPERATORS.remove(deps.storage, (&info.sender, &operator));
+
+    let evt = Event::new("revoke-all")
+        .add_attribute("sender", info.sender)
+        .add_attribute("operator", operator);
+    Ok(Response::new().add_event(evt))
+}
+
+fn owner_of(invest: &Investment, env: &Env, include_expired: bool) -> OwnerOfResponse {
+    let approvals = invest
+        .approvals
+        .iter()
+        .filter(|a| include_expired || !a.expires.is_expired(&env.block))
+        .cloned()
+        .collect();
+    OwnerOfResponse {
+        owner: invest.investor.clone(),
+        approvals,
+    }
+}
+
+pub fn query_owner_of(
+    deps: Deps,
+    env: Env,
+    token_id: String,
+    include_expired: Option<bool>,
+) -> Result<OwnerOfResponse, ContractError> {
+    let invest = load_token(deps, &token_id)?;
+    Ok(owner_of(&invest, &env, include_expired.unwrap_or(false)))
+}
+
+pub fn query_all_operators(
+    deps: Deps,
+    env: Env,
+    owner: String,
+    include_expired: Option<bool>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<OperatorsResponse, ContractError> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let include_expired = include_expired.unwrap_or(false);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?
+        .map(|addr| Bound::exclusive(addr.as_bytes()));
+
+    let operators: StdResult<Vec<_>> = OPERATORS
+        .prefix_de(&owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|res| match res {
+            Ok((_, expires)) => include_expired || !expires.is_expired(&env.block),
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|res| {
+            let (spender, expires) = res?;
+            Ok(Approval { spender, expires })
+        })
+        .collect();
+    Ok(OperatorsResponse {
+        operators: operators?,
+    })
+}
+
+pub fn query_num_tokens(deps: Deps) -> Result<NumTokensResponse, ContractError> {
+    let count = OPEN_INVESTMENTS.may_load(deps.storage)?.unwrap_or_default();
+    Ok(NumTokensResponse { count })
+}
+
+pub fn query_contract_info() -> ContractInfoResponse {
+    ContractInfoResponse {
+        name: NFT_NAME.to_string(),
+        symbol: NFT_SYMBOL.to_string(),
+    }
+}
+
+pub fn query_nft_info(deps: Deps, token_id: String) -> Result<NftInfoResponse, ContractError> {
+    Ok(load_token(deps, &token_id)?.into())
+}
+
+pub fn query_all_nft_info(
+    deps: Deps,
+    env: Env,
+    token_id: String,
+    include_expired: Option<bool>,
+) -> Result<AllNftInfoResponse, ContractError> {
+    let invest = load_token(deps, &token_id)?;
+    Ok(AllNftInfoResponse {
+        access: owner_of(&invest, &env, include_expired.unwrap_or(false)),
+        info: invest.into(),
+    })
+}
+
+pub fn query_tokens(
+    deps: Deps,
+    owner: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<TokensResponse, ContractError> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|id| parse_token_id(&id))
+        .transpose()?
+        .map(Bound::exclusive_int);
+
+    let tokens: StdResult<Vec<_>> = investments()
+        .idx
+        .investor
+        .prefix(owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|res| res.map(|(_, invest)| invest.id.to_string()))
+        .collect();
+    Ok(TokensResponse { tokens: tokens? })
+}
+
+pub fn query_all_tokens(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<TokensResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|id| parse_token_id(&id))
+        .transpose()?
+        .map(Bound::exclusive_int);
+
+    let tokens: StdResult<Vec<_>> = investments()
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|res| res.map(|(_, invest)| invest.id.to_string()))
+        .collect();
+    Ok(TokensResponse { tokens: tokens? })
+}
@@ -0,0 +1,337 @@
+use std::convert::TryInto;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Decimal, OverflowError, OverflowOperation, Uint128, Uint256};
+
+use crate::ContractError;
+
+// atomics of Decimal::one()
+const ONE: u128 = 1_000_000_000_000_000_000;
+// ln(2) in atomics
+const LN_2: u128 = 693_147_180_559_945_309;
+
+/// Whether a falling or a rising index is good for the investors
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexDirection {
+    /// eg. emissions or temperature: ratio = baseline / index
+    LowerIsBetter,
+    /// eg. vegetation cover or soil moisture: ratio = index / baseline
+    HigherIsBetter,
+}
+
+impl Default for IndexDirection {
+    fn default() -> Self {
+        IndexDirection::LowerIsBetter
+    }
+}
+
+impl IndexDirection {
+    /// How much the index improved from the baseline, see `index_ratio`
+    pub fn ratio(self, baseline: Decimal, index: Decimal) -> Decimal {
+        match self {
+            IndexDirection::LowerIsBetter => index_ratio(baseline, index),
+            IndexDirection::HigherIsBetter => index_ratio(index, baseline),
+        }
+    }
+}
+
+/// How the payout multiplier follows the index. All curves are in terms of the ratio
+/// of the index to the baseline (see `IndexDirection`), which grows as the index improves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutCurve {
+    /// multiplier = ratio
+    Linear {},
+    /// multiplier = ratio, but never below min or above max
+    Capped { min: Decimal, max: Decimal },
+    /// multiplier of the highest step whose ratio is reached, nothing below the first step
+    Step { steps: Vec<PayoutStep> },
+    /// multiplier = 1 + scale * ln(ratio), but never below zero
+    Logarithmic { scale: Decimal },
+}
+
+impl Default for PayoutCurve {
+    fn default() -> Self {
+        PayoutCurve::Linear {}
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PayoutStep {
+    // minimum ratio (see `IndexDirection::ratio`) to reach this step
+    pub ratio: Decimal,
+    // multiplier paid out from this step on
+    pub multiplier: Decimal,
+}
+
+impl PayoutCurve {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        match self {
+            PayoutCurve::Linear {} | PayoutCurve::Logarithmic { .. } => Ok(()),
+            PayoutCurve::Capped { min, max } if min > max => Err(
+                ContractError::InvalidPayoutCurve("min is above max".to_string()),
+            ),
+            PayoutCurve::Capped { .. } => Ok(()),
+            PayoutCurve::Step { steps } if steps.is_empty() => Err(
+                ContractError::InvalidPayoutCurve("needs at least one step".to_string()),
+            ),
+            PayoutCurve::Step { steps } => {
+                if steps.windows(2).any(|w| w[0].ratio >= w[1].ratio) {
+                    return Err(ContractError::InvalidPayoutCurve(
+                        "step ratios must be increasing".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The highest multiplier this curve can reach, None if it is unbounded
+    pub fn max_multiplier(&self) -> Option<Decimal> {
+        match self {
+            PayoutCurve::Linear {} | PayoutCurve::Logarithmic { .. } => None,
+            PayoutCurve::Capped { max, .. } => Some(*max),
+            PayoutCurve::Step { steps } => steps.iter().map(|s| s.multiplier).max(),
+        }
+    }
+
+    /// The multiplier to pay out at the given ratio (see `index_ratio`)
+    pub fn multiplier(&self, ratio: Decimal) -> Result<Decimal, ContractError> {
+        match self {
+            PayoutCurve::Linear {} => Ok(ratio),
+            PayoutCurve::Capped { min, max } => Ok(ratio.max(*min).min(*max)),
+            PayoutCurve::Step { steps } => Ok(steps
+                .iter()
+                .rev()
+                .find(|s| s.ratio <= ratio)
+                .map(|s| s.multiplier)
+                .unwrap_or_else(Decimal::zero)),
+            PayoutCurve::Logarithmic { scale } => {
+                if ratio.is_zero() {
+                    return Ok(Decimal::zero());
+                }
+                let (negative, ln) = ln(ratio);
+                let change = checked_mul_decimal(ln, *scale)?.atomics();
+                let one = Decimal::one().atomics();
+                let multiplier = if negative {
+                    one.saturating_sub(change)
+                } else {
+                    one.checked_add(change)?
+                };
+                Ok(from_atomics(multiplier))
+            }
+        }
+    }
+}
+
+/// a / b, saturating at Decimal::MAX when b is zero or tiny
+pub fn index_ratio(a: Decimal, b: Decimal) -> Decimal {
+    if b.is_zero() {
+        return Decimal::MAX;
+    }
+    let ratio = a.atomics().full_mul(ONE) / Uint256::from(b.atomics());
+    ratio.try_into().map(from_atomics).unwrap_or(Decimal::MAX)
+}
+
+/// amount * multiplier, returning an error rather than panicking on overflow
+pub fn checked_mul(amount: Uint128, multiplier: Decimal) -> Result<Uint128, ContractError> {
+    let result = amount.full_mul(multiplier.atomics()) / Uint256::from(ONE);
+    result.try_into().map_err(|_| {
+        ContractError::from(OverflowError::new(
+            OverflowOperation::Mul,
+            amount,
+            multiplier,
+        ))
+    })
+}
+
+fn checked_mul_decimal(a: Decimal, b: Decimal) -> Result<Decimal, ContractError> {
+    checked_mul(a.atomics(), b).map(from_atomics)
+}
+
+pub fn from_atomics(atomics: Uint128) -> Decimal {
+    Decimal::from_ratio(atomics, ONE)
+}
+
+/// Natural logarithm as (is negative, absolute value), x must not be zero
+fn ln(x: Decimal) -> (bool, Decimal) {
+    if x >= Decimal::one() {
+        (false, ln_above_one(x.atomics().u128()))
+    } else {
+        // ln(x) = -ln(1/x)
+        let inverse = index_ratio(Decimal::one(), x);
+        (true, ln_above_one(inverse.atomics().u128()))
+    }
+}
+
+/// ln(x) for x >= 1 in atomics, via ln(x) = log2(x) * ln(2)
+fn ln_above_one(x: u128) -> Decimal {
+    // integer part of log2, bringing x into [1, 2)
+    let mut y = x;
+    let mut log2 = 0u128;
+    while y >= 2 * ONE {
+        y /= 2;
+        log2 += ONE;
+    }
+    // fractional part, one bit per squaring
+    let mut bit = ONE / 2;
+    while bit > 0 {
+        y = y * y / ONE;
+        if y >= 2 * ONE {
+            y /= 2;
+            log2 += bit;
+        }
+        bit /= 2;
+    }
+    from_atomics(Uint128::new(log2 * LN_2 / ONE))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn step(ratio: u64, multiplier: u64) -> PayoutStep {
+        PayoutStep {
+            ratio: Decimal::percent(ratio),
+            multiplier: Decimal::percent(multiplier),
+        }
+    }
+
+    // equal up to the last few atomics lost in the fixed point math
+    fn assert_near(a: Decimal, b: Decimal) {
+        let (x, y) = (a.atomics().u128(), b.atomics().u128());
+        let diff = x.max(y) - x.min(y);
+        assert!(diff < 1000, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn ratio_never_panics() {
+        let ratio = index_ratio(Decimal::percent(450), Decimal::percent(300));
+        assert_eq!(ratio, Decimal::percent(150));
+        assert_eq!(index_ratio(Decimal::one(), Decimal::zero()), Decimal::MAX);
+        assert_eq!(
+            index_ratio(Decimal::MAX, Decimal::permille(1)),
+            Decimal::MAX
+        );
+        assert_eq!(
+            index_ratio(Decimal::zero(), Decimal::one()),
+            Decimal::zero()
+        );
+
+        assert_eq!(
+            checked_mul(Uint128::new(1000), Decimal::percent(150)).unwrap(),
+            Uint128::new(1500)
+        );
+        checked_mul(Uint128::MAX, Decimal::percent(200)).unwrap_err();
+    }
+
+    #[test]
+    fn index_direction() {
+        let baseline = Decimal::percent(400);
+        let lower = IndexDirection::LowerIsBetter;
+        let higher = IndexDirection::HigherIsBetter;
+        assert_eq!(
+            lower.ratio(baseline, Decimal::percent(200)),
+            Decimal::percent(200)
+        );
+        assert_eq!(
+            higher.ratio(baseline, Decimal::percent(200)),
+            Decimal::percent(50)
+        );
+        assert_eq!(
+            higher.ratio(baseline, Decimal::percent(600)),
+            Decimal::percent(150)
+        );
+        assert_eq!(higher.ratio(Decimal::zero(), Decimal::one()), Decimal::MAX);
+    }
+
+    #[test]
+    fn linear_and_capped() {
+        let linear = PayoutCurve::Linear {};
+        assert_eq!(linear.max_multiplier(), None);
+        assert_eq!(
+            linear.multiplier(Decimal::percent(250)).unwrap(),
+            Decimal::percent(250)
+        );
+
+        let capped = PayoutCurve::Capped {
+            min: Decimal::percent(50),
+            max: Decimal::percent(200),
+        };
+        capped.validate().unwrap();
+        assert_eq!(capped.max_multiplier(), Some(Decimal::percent(200)));
+        let mult = |r| capped.multiplier(Decimal::percent(r)).unwrap();
+        assert_eq!(mult(10), Decimal::percent(50));
+        assert_eq!(mult(120), Decimal::percent(120));
+        assert_eq!(mult(500), Decimal::percent(200));
+        assert_eq!(
+            capped.multiplier(Decimal::MAX).unwrap(),
+            Decimal::percent(200)
+        );
+
+        let invalid = PayoutCurve::Capped {
+            min: Decimal::percent(300),
+            max: Decimal::percent(200),
+        };
+        invalid.validate().unwrap_err();
+    }
+
+    #[test]
+    fn step_function() {
+        let curve = PayoutCurve::Step {
+            steps: vec![step(80, 50), step(100, 100), step(150, 200)],
+        };
+        curve.validate().unwrap();
+        assert_eq!(curve.max_multiplier(), Some(Decimal::percent(200)));
+        let mult = |r| curve.multiplier(Decimal::percent(r)).unwrap();
+        assert_eq!(mult(79), Decimal::zero());
+        assert_eq!(mult(80), Decimal::percent(50));
+        assert_eq!(mult(149), Decimal::percent(100));
+        assert_eq!(mult(1000), Decimal::percent(200));
+
+        let empty = PayoutCurve::Step { steps: vec![] };
+        empty.validate().unwrap_err();
+        let unordered = PayoutCurve::Step {
+            steps: vec![step(100, 100), step(100, 200)],
+        };
+        unordered.validate().unwrap_err();
+    }
+
+    #[test]
+    fn logarithmic() {
+        let curve = PayoutCurve::Logarithmic {
+            scale: Decimal::one(),
+        };
+        assert_eq!(curve.max_multiplier(), None);
+        let mult = |r| curve.multiplier(r).unwrap();
+        // unchanged index pays back the investment
+        assert_eq!(mult(Decimal::one()), Decimal::one());
+        // ln(2) = 0.693147...
+        assert_near(
+            mult(Decimal::percent(200)),
+            Decimal::from_ratio(1_693_147_180_559_945_309u128, ONE),
+        );
+        // ln(0.5) = -0.693147...
+        assert_near(
+            mult(Decimal::percent(50)),
+            Decimal::from_ratio(306_852_819_440_054_691u128, ONE),
+        );
+        // ln(e^2) = 2
+        assert_near(
+            mult(Decimal::from_ratio(7_389_056_098_930_650_227u128, ONE)),
+            Decimal::percent(300),
+        );
+        // never below zero, and no panic at the extremes
+        assert_eq!(mult(Decimal::percent(10)), Decimal::zero());
+        assert_eq!(mult(Decimal::zero()), Decimal::zero());
+        mult(Decimal::MAX);
+
+        let huge = PayoutCurve::Logarithmic {
+            scale: Decimal::MAX,
+        };
+        huge.multiplier(Decimal::MAX).unwrap_err();
+    }
+}
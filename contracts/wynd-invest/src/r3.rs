@@ -1,14 +1,134 @@
+//! Locations are H3 cells, given as the 15 character hex form of the 64 bit index.
+//! The index holds (from the high bits) a reserved bit, the mode (1 for cells), three
+//! reserved bits, the resolution, the base cell and then one 3 bit digit per resolution,
+//! with 7 marking the digits below the resolution of the cell.
+
 use crate::ContractError;
 
 pub type R3 = String;
 
+/// The finest resolution of the grid
+pub const MAX_RESOLUTION: u8 = 15;
+
+const CELL_MODE: u64 = 1;
+const MODE_OFFSET: u32 = 59;
+const RESERVED_OFFSET: u32 = 56;
+const RES_OFFSET: u32 = 52;
+const BASE_CELL_OFFSET: u32 = 45;
+const NUM_BASE_CELLS: u8 = 122;
+
+const DIGIT_BITS: u32 = 3;
+const DIGIT_MASK: u64 = 7;
+const UNUSED_DIGIT: u64 = 7;
+// pentagons have no children in this direction, so it may not be the first non-zero digit
+const K_AXES_DIGIT: u64 = 1;
+const PENTAGONS: [u8; 12] = [4, 14, 24, 38, 49, 58, 63, 72, 83, 97, 107, 117];
+
 pub fn validate_r3(input: String) -> Result<R3, ContractError> {
-    let lower = input.to_lowercase();
-    if lower.len() != 15 || !is_hex(&lower) {
-        Err(ContractError::InvalidR3(input))
-    } else {
-        Ok(lower)
+    decode(&input)?;
+    Ok(input.to_lowercase())
+}
+
+/// The resolution of the cell, from 0 (the 122 base cells) to 15
+pub fn resolution(hex: &str) -> Result<u8, ContractError> {
+    Ok(get_resolution(decode(hex)?))
+}
+
+/// The cell at the given (coarser) resolution which contains this one
+pub fn parent(hex: &str, res: u8) -> Result<R3, ContractError> {
+    let index = decode(hex)?;
+    let own = get_resolution(index);
+    if res > own {
+        return Err(ContractError::InvalidResolution(res));
+    }
+    let parent = (res + 1..=own).fold(set_resolution(index, res), |cell, r| {
+        set_digit(cell, r, UNUSED_DIGIT)
+    });
+    Ok(encode(parent))
+}
+
+/// All cells at the given (finer) resolution within this one, in ascending order.
+/// Every step of resolution multiplies the number of cells by (about) 7.
+pub fn children(hex: &str, res: u8) -> Result<Vec<R3>, ContractError> {
+    let index = decode(hex)?;
+    let own = get_resolution(index);
+    if res < own || res > MAX_RESOLUTION {
+        return Err(ContractError::InvalidResolution(res));
+    }
+    let mut cells = vec![index];
+    for r in own + 1..=res {
+        cells = cells
+            .into_iter()
+            .flat_map(|cell| {
+                let cell = set_resolution(cell, r);
+                (0..UNUSED_DIGIT).map(move |digit| set_digit(cell, r, digit))
+            })
+            .filter(|&cell| is_valid_cell(cell))
+            .collect();
     }
+    Ok(cells.into_iter().map(encode).collect())
+}
+
+fn decode(input: &str) -> Result<u64, ContractError> {
+    let invalid = || ContractError::InvalidR3(input.to_string());
+    if input.len() != 15 || !is_hex(&input.to_lowercase()) {
+        return Err(invalid());
+    }
+    let index = u64::from_str_radix(input, 16).map_err(|_| invalid())?;
+    match is_valid_cell(index) {
+        true => Ok(index),
+        false => Err(invalid()),
+    }
+}
+
+fn encode(index: u64) -> R3 {
+    format!("{:015x}", index)
+}
+
+fn is_valid_cell(index: u64) -> bool {
+    if (index >> MODE_OFFSET) != CELL_MODE || (index >> RESERVED_OFFSET) & 7 != 0 {
+        return false;
+    }
+    let base_cell = ((index >> BASE_CELL_OFFSET) & 0x7f) as u8;
+    if base_cell >= NUM_BASE_CELLS {
+        return false;
+    }
+    let res = get_resolution(index);
+    let mut leading_zeros = PENTAGONS.contains(&base_cell);
+    for r in 1..=MAX_RESOLUTION {
+        let digit = get_digit(index, r);
+        if r > res {
+            if digit != UNUSED_DIGIT {
+                return false;
+            }
+        } else if digit == UNUSED_DIGIT || (leading_zeros && digit == K_AXES_DIGIT) {
+            return false;
+        } else if digit != 0 {
+            leading_zeros = false;
+        }
+    }
+    true
+}
+
+fn get_resolution(index: u64) -> u8 {
+    ((index >> RES_OFFSET) & 0xf) as u8
+}
+
+fn set_resolution(index: u64, res: u8) -> u64 {
+    (index & !(0xf << RES_OFFSET)) | ((res as u64) << RES_OFFSET)
+}
+
+fn digit_offset(res: u8) -> u32 {
+    (MAX_RESOLUTION - res) as u32 * DIGIT_BITS
+}
+
+fn get_digit(index: u64, res: u8) -> u64 {
+    (index >> digit_offset(res)) & DIGIT_MASK
+}
+
+fn set_digit(index: u64, res: u8, digit: u64) -> u64 {
+    let offset = digit_offset(res);
+    (index & !(DIGIT_MASK << offset)) | (digit << offset)
 }
 
 fn is_hex(input: &str) -> bool {
@@ -32,13 +152,87 @@ mod test {
         // Too long
         validate_r3("1234567890abcdef".into()).unwrap_err();
         // too short
-        validate_r3("8362718fffffff".into()).unwrap_err();
-        // real one
-        validate_r3("8362718ffffffff".into()).unwrap();
+        validate_r3("832830ffffffff".into()).unwrap_err();
+        // real ones
+        validate_r3("832830fffffffff".into()).unwrap();
+        validate_r3("8928308280fffff".into()).unwrap();
+        validate_r3("80c3fffffffffff".into()).unwrap();
         // allow uppercase, but convert it to lowercase
         assert_eq!(
-            validate_r3("8362718FFFFFFFF".into()).unwrap().as_str(),
-            "8362718ffffffff"
+            validate_r3("832830FFFFFFFFF".into()).unwrap().as_str(),
+            "832830fffffffff"
         );
     }
+
+    #[test]
+    fn validate_r3_decodes_cell() {
+        let invalid = |hex: &str| {
+            let err = validate_r3(hex.into()).unwrap_err();
+            assert_eq!(err, ContractError::InvalidR3(hex.to_string()));
+        };
+        // not the cell mode
+        invalid("032830fffffffff");
+        invalid("432830fffffffff");
+        // reserved bits set
+        invalid("932830fffffffff");
+        invalid("a32830fffffffff");
+        // base cell 122 is out of range
+        invalid("80f5fffffffffff");
+        // a used digit of 7
+        invalid("83283ffffffffff");
+        // an unused digit (below resolution 3) which is not 7
+        invalid("8328308ffffffff");
+        // pentagon (base cell 4) with 1 as first non-zero digit
+        invalid("81087ffffffffff");
+        invalid("82080ffffffffff");
+        // other digits are fine on pentagons
+        validate_r3("81083ffffffffff".into()).unwrap();
+        validate_r3("8108bffffffffff".into()).unwrap();
+        validate_r3("820827fffffffff".into()).unwrap();
+    }
+
+    #[test]
+    fn resolution_works() {
+        assert_eq!(resolution("8009fffffffffff").unwrap(), 0);
+        assert_eq!(resolution("832830fffffffff").unwrap(), 3);
+        assert_eq!(resolution("8928308280FFFFF").unwrap(), 9);
+        resolution("foobar").unwrap_err();
+    }
+
+    #[test]
+    fn parent_works() {
+        let hex = "8928308280fffff";
+        assert_eq!(parent(hex, 9).unwrap(), hex);
+        assert_eq!(parent(hex, 7).unwrap(), "872830828ffffff");
+        assert_eq!(parent(hex, 3).unwrap(), "832830fffffffff");
+        assert_eq!(parent(hex, 0).unwrap(), "8029fffffffffff");
+        let err = parent("832830fffffffff", 4).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolution(4));
+    }
+
+    #[test]
+    fn children_works() {
+        let hex = "872830828ffffff";
+        assert_eq!(children(hex, 7).unwrap(), vec![hex.to_string()]);
+
+        let kids = children(hex, 8).unwrap();
+        assert_eq!(kids.len(), 7);
+        assert_eq!(kids[0], "8828308281fffff");
+        assert_eq!(kids[6], "882830828dfffff");
+        for kid in kids.iter() {
+            assert_eq!(parent(kid, 7).unwrap(), hex);
+        }
+        assert_eq!(children(hex, 9).unwrap().len(), 49);
+
+        // pentagons have one child less
+        let kids = children("8009fffffffffff", 1).unwrap();
+        assert_eq!(kids.len(), 6);
+        assert!(!kids.contains(&"81087ffffffffff".to_string()));
+        assert_eq!(children("8009fffffffffff", 2).unwrap().len(), 6 * 7 - 1);
+
+        let err = children(hex, 6).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolution(6));
+        let err = children(hex, 16).unwrap_err();
+        assert_eq!(err, ContractError::InvalidResolution(16));
+    }
 }
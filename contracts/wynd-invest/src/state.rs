@@ -1,35 +1,289 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+use crate::payout::{checked_mul, from_atomics, index_ratio, IndexDirection, PayoutCurve};
+use crate::r3::{resolution, MAX_RESOLUTION};
 use crate::ContractError;
-use cosmwasm_std::{Addr, Decimal, Env, Fraction, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{
+    Addr, Api, Binary, Decimal, Env, Order, OverflowError, OverflowOperation, StdResult, Storage,
+    Uint128,
+};
+use cw0::Expiration;
+use cw20::Denom;
+use cw_storage_plus::{Bound, Index, IndexList, IndexedMap, Item, Map, MultiIndex, U64Key};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
+    // address that can add, retire and re-open locations (if any)
+    pub admin: Option<Addr>,
+    // address that can pause and unpause parts of the contract in an emergency (as can the admin)
+    #[serde(default)]
+    pub pauser: Option<Addr>,
     // address of oracle contract (this allows writing data)
     pub oracle: Addr,
-    // address of the cw20 token that we use for payment
-    pub token: Addr,
+    // further oracles, each submitting values for the same hexes and times
+    #[serde(default)]
+    pub oracles: Vec<Addr>,
+    // how many oracles must submit a value for a hex and time before the median is stored
+    #[serde(default = "default_quorum")]
+    pub quorum: u32,
+    // limits on how oracle values may change, to reject outliers
+    #[serde(default)]
+    pub oracle_guards: OracleGuards,
+    // the resolutions of r3 locations that may be added
+    #[serde(default)]
+    pub resolutions: ResolutionRange,
+    // the native or cw20 tokens that we accept for payment.
+    // these should be of equal value (eg. stablecoins), as max_investment_hex applies to the sum
+    pub tokens: Vec<Denom>,
     // maximum amount that can be invested in one hex
     pub max_investment_hex: Uint128,
-    // how many days the investment takes until maturity (eg. we pay out in the results in 30 days, 180 days)
+    // how many days the investment takes until maturity (eg. we pay out in the results in 30 days, 180 days),
+    // unless the investor picks another tier
     pub maturity_days: u64,
+    // all maturities (in days) investors can pick from, including maturity_days
+    #[serde(default)]
+    pub maturity_tiers: Vec<u64>,
     // how many days margin we have from measurement to usage.
     // when investing, the latest data must be within X days
     // when investment finishes, there must be data within X days of maturity
     pub measurement_window: u64,
+    // the most an investment can pay out, as a multiple of the amount invested (at least 1).
+    // this bounds the liabilities the reserves must cover
+    #[serde(default = "default_max_payout")]
+    pub max_payout: Decimal,
+    // how the payout follows the index, always capped by max_payout
+    #[serde(default)]
+    pub payout_curve: PayoutCurve,
+    // whether a falling or a rising index is good for the investors
+    #[serde(default)]
+    pub index_direction: IndexDirection,
+    // terms for exiting before maturity, not allowed if unset
+    #[serde(default)]
+    pub early_exit: Option<EarlyExit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct OracleGuards {
+    // the most a value may differ from the last measurement, as a share of it
+    pub max_deviation: Option<Decimal>,
+    // the least time (in seconds) between two measurements of a location
+    pub min_interval: Option<u64>,
+}
+
+impl OracleGuards {
+    /// rejects a value that is too soon after, or too far from, the last measurement
+    pub fn check(
+        &self,
+        last: &Measurement,
+        value: Decimal,
+        time: u64,
+    ) -> Result<(), ContractError> {
+        if let Some(min_interval) = self.min_interval {
+            let interval = time.saturating_sub(last.time);
+            if interval < min_interval {
+                return Err(ContractError::UpdateTooSoon {
+                    interval,
+                    min_interval,
+                });
+            }
+        }
+        if let Some(max_deviation) = self.max_deviation {
+            let diff = match value > last.value {
+                true => value - last.value,
+                false => last.value - value,
+            };
+            // any change from zero is too much
+            if !diff.is_zero() && index_ratio(diff, last.value) > max_deviation {
+                return Err(ContractError::DeviationTooLarge {
+                    value,
+                    previous: last.value,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn default_quorum() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EarlyExit {
+    // share of the amount kept when exiting right after investing (at most 1),
+    // decaying linearly to zero at maturity
+    pub penalty: Decimal,
+    // receives the penalties, if unset they are added to the reserve
+    pub fee_collector: Option<Addr>,
+}
+
+impl EarlyExit {
+    /// how much of the investment is kept when exiting at the time of `env`
+    pub fn penalty_for(&self, invest: &Investment, env: &Env) -> Result<Uint128, ContractError> {
+        let now = env.block.time.seconds();
+        let remaining = invest.maturity_time.saturating_sub(now);
+        let duration = invest.maturity_time - invest.invested_time;
+        if duration == 0 {
+            return Ok(Uint128::zero());
+        }
+        let full = checked_mul(invest.amount, self.penalty)?;
+        Ok(full.multiply_ratio(remaining, duration))
+    }
+}
+
+impl Config {
+    /// whether this address may submit oracle values
+    pub fn is_oracle(&self, addr: &Addr) -> bool {
+        &self.oracle == addr || self.oracles.contains(addr)
+    }
+
+    /// the quorum must be reachable by the oracles, which must all be different
+    pub fn validate_oracles(&self) -> Result<(), ContractError> {
+        for (i, oracle) in self.oracles.iter().enumerate() {
+            if oracle == &self.oracle || self.oracles[..i].contains(oracle) {
+                return Err(ContractError::DuplicateOracle(oracle.to_string()));
+            }
+        }
+        if self.quorum == 0 || self.quorum as usize > self.oracles.len() + 1 {
+            return Err(ContractError::InvalidQuorum(self.quorum));
+        }
+        Ok(())
+    }
+
+    /// days until maturity for a new investment, maturity_days unless another tier is picked
+    pub fn maturity_for(&self, days: Option<u64>) -> Result<u64, ContractError> {
+        match days {
+            None => Ok(self.maturity_days),
+            Some(days) if days == self.maturity_days || self.maturity_tiers.contains(&days) => {
+                Ok(days)
+            }
+            Some(days) => Err(ContractError::InvalidMaturity(days)),
+        }
+    }
+
+    /// the most any investment can pay out, as a multiple of the amount invested
+    pub fn max_multiplier(&self) -> Decimal {
+        match self.payout_curve.max_multiplier() {
+            Some(max) if max < self.max_payout => max,
+            _ => self.max_payout,
+        }
+    }
+}
+
+/// Payout cap for configs stored before it was introduced
+pub fn default_max_payout() -> Decimal {
+    Decimal::percent(200)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
 pub struct Location {
     pub cur_index: Option<Measurement>,
-    // amount of money invested here
+    // retired locations accept no new investments, but existing ones still settle
+    #[serde(default)]
+    pub retired: bool,
+    // amount of money invested here, summed over all tokens
     pub total_invested: Uint128,
     pub current_invested: Uint128,
     // number of individual investments made (people)
     pub total_investments: u64,
     pub current_investments: u64,
+    // amount of money invested here in each token
+    #[serde(default)]
+    pub tokens: Vec<TokenTotals>,
+    // amount of money invested here in each maturity tier
+    #[serde(default)]
+    pub tiers: Vec<TierTotals>,
+    // oracle values outside of this range are rejected (if set)
+    #[serde(default)]
+    pub value_range: Option<ValueRange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ValueRange {
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+impl ValueRange {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        match self.min > self.max {
+            true => Err(ContractError::InvalidValueRange),
+            false => Ok(()),
+        }
+    }
+
+    pub fn check(&self, value: Decimal) -> Result<(), ContractError> {
+        match value < self.min || value > self.max {
+            true => Err(ContractError::ValueOutOfRange(value)),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ResolutionRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Default for ResolutionRange {
+    fn default() -> Self {
+        ResolutionRange {
+            min: 0,
+            max: MAX_RESOLUTION,
+        }
+    }
+}
+
+impl ResolutionRange {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        match self.min > self.max || self.max > MAX_RESOLUTION {
+            true => Err(ContractError::InvalidResolutionRange),
+            false => Ok(()),
+        }
+    }
+
+    pub fn check(&self, hex: &str) -> Result<(), ContractError> {
+        let res = resolution(hex)?;
+        match res < self.min || res > self.max {
+            true => Err(ContractError::ResolutionOutOfRange {
+                hex: hex.to_string(),
+                min: self.min,
+                max: self.max,
+            }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenTotals {
+    pub token: Denom,
+    pub total_invested: Uint128,
+    pub current_invested: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TierTotals {
+    pub maturity_days: u64,
+    pub total_invested: Uint128,
+    pub current_invested: Uint128,
+    pub total_investments: u64,
+    pub current_investments: u64,
+}
+
+impl TierTotals {
+    pub fn new(maturity_days: u64) -> Self {
+        TierTotals {
+            maturity_days,
+            total_invested: Uint128::zero(),
+            current_invested: Uint128::zero(),
+            total_investments: 0,
+            current_investments: 0,
+        }
+    }
 }
 
 impl Location {
@@ -37,16 +291,174 @@ impl Location {
         Self::default()
     }
 
-    pub fn add_investment(&mut self, amount: Uint128) {
+    /// how much more can be invested here before hitting the max
+    pub fn remaining_capacity(&self, cfg: &Config) -> Uint128 {
+        cfg.max_investment_hex.saturating_sub(self.current_invested)
+    }
+
+    pub fn add_investment(&mut self, token: &Denom, maturity_days: u64, amount: Uint128) {
         self.total_invested += amount;
         self.current_invested += amount;
         self.total_investments += 1;
         self.current_investments += 1;
+        match self.tokens.iter_mut().find(|t| &t.token == token) {
+            Some(totals) => {
+                totals.total_invested += amount;
+                totals.current_invested += amount;
+            }
+            None => self.tokens.push(TokenTotals {
+                token: token.clone(),
+                total_invested: amount,
+                current_invested: amount,
+            }),
+        }
+        let tier = match self
+            .tiers
+            .iter()
+            .position(|t| t.maturity_days == maturity_days)
+        {
+            Some(idx) => &mut self.tiers[idx],
+            None => {
+                self.tiers.push(TierTotals::new(maturity_days));
+                self.tiers.last_mut().unwrap()
+            }
+        };
+        tier.total_invested += amount;
+        tier.current_invested += amount;
+        tier.total_investments += 1;
+        tier.current_investments += 1;
     }
 
-    pub fn finish_investment(&mut self, amount: Uint128, count: u64) -> Result<(), ContractError> {
+    pub fn finish_investment(
+        &mut self,
+        token: &Denom,
+        maturity_days: u64,
+        amount: Uint128,
+        count: u64,
+    ) -> Result<(), ContractError> {
         self.current_investments -= count;
         self.current_invested = self.current_invested.checked_sub(amount)?;
+        let totals = self
+            .tokens
+            .iter_mut()
+            .find(|t| &t.token == token)
+            .ok_or_else(|| ContractError::InvalidToken(denom_name(token)))?;
+        totals.current_invested = totals.current_invested.checked_sub(amount)?;
+        // locations from before the tiers don't track them
+        if let Some(tier) = self
+            .tiers
+            .iter_mut()
+            .find(|t| t.maturity_days == maturity_days)
+        {
+            tier.current_invested = tier.current_invested.checked_sub(amount)?;
+            tier.current_investments -= count;
+        }
+        Ok(())
+    }
+}
+
+/// Human readable name of the token: native denom or cw20 address
+pub fn denom_name(token: &Denom) -> String {
+    match token {
+        Denom::Native(denom) => denom.clone(),
+        Denom::Cw20(addr) => addr.to_string(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PauseFlags {
+    // no new investments
+    pub investments: bool,
+    // the oracle cannot store values
+    pub oracle: bool,
+    // no withdrawals or early exits
+    pub withdrawals: bool,
+}
+
+impl PauseFlags {
+    pub fn load(storage: &dyn Storage) -> StdResult<Self> {
+        Ok(PAUSED.may_load(storage)?.unwrap_or_default())
+    }
+
+    pub fn assert_investments(&self) -> Result<(), ContractError> {
+        match self.investments {
+            true => Err(ContractError::Paused("investments".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    pub fn assert_oracle(&self) -> Result<(), ContractError> {
+        match self.oracle {
+            true => Err(ContractError::Paused("oracle".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    pub fn assert_withdrawals(&self) -> Result<(), ContractError> {
+        match self.withdrawals {
+            true => Err(ContractError::Paused("withdrawals".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Reserve {
+    pub token: Denom,
+    // funds set aside to pay out rewards above the amount invested.
+    // investors' losses are added here, their gains are paid from here
+    pub reserve: Uint128,
+    // amount of all open investments in this token
+    pub invested: Uint128,
+}
+
+impl Reserve {
+    pub fn new(token: Denom) -> Self {
+        Reserve {
+            token,
+            reserve: Uint128::zero(),
+            invested: Uint128::zero(),
+        }
+    }
+
+    /// loads the ledger of this token, empty if nothing was funded or invested yet
+    pub fn load(storage: &dyn Storage, token: &Denom) -> StdResult<Self> {
+        Ok(RESERVES
+            .may_load(storage, &denom_name(token))?
+            .unwrap_or_else(|| Reserve::new(token.clone())))
+    }
+
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        RESERVES.save(storage, &denom_name(&self.token), self)
+    }
+
+    /// worst-case payout above the amount invested, if every open investment paid the max
+    pub fn liabilities(&self, cfg: &Config) -> Uint128 {
+        let max = cfg.max_multiplier();
+        if max > Decimal::one() {
+            self.invested * (max - Decimal::one())
+        } else {
+            Uint128::zero()
+        }
+    }
+
+    /// whether the reserve covers the worst-case liabilities
+    pub fn is_solvent(&self, cfg: &Config) -> bool {
+        self.liabilities(cfg) <= self.reserve
+    }
+
+    /// moves an investment out of the ledger, paying `reward` for the `amount` invested.
+    /// fails if the reserve cannot pay the gain, so the investment stays open until it is funded
+    pub fn settle(&mut self, amount: Uint128, reward: Uint128) -> Result<(), ContractError> {
+        self.invested = self.invested.checked_sub(amount)?;
+        if reward > amount {
+            self.reserve = self
+                .reserve
+                .checked_sub(reward - amount)
+                .map_err(|_| ContractError::InsufficientReserve(denom_name(&self.token)))?;
+        } else {
+            self.reserve += amount - reward;
+        }
         Ok(())
     }
 }
@@ -64,8 +476,81 @@ impl Measurement {
     }
 }
 
+/// Public key an oracle signs its values with, so anyone can relay them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OracleKey {
+    /// signs the sha256 hash of the payload, compressed (33 bytes) or uncompressed (65 bytes) key
+    Secp256k1 { pubkey: Binary },
+    /// signs the payload itself, 32 byte key
+    Ed25519 { pubkey: Binary },
+}
+
+impl OracleKey {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        let valid = match self {
+            OracleKey::Secp256k1 { pubkey } => matches!(pubkey.len(), 33 | 65),
+            OracleKey::Ed25519 { pubkey } => pubkey.len() == 32,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(ContractError::InvalidOracleKey),
+        }
+    }
+
+    /// checks the signature over the payload, see `signed_payload`
+    pub fn verify(
+        &self,
+        api: &dyn Api,
+        payload: &[u8],
+        signature: &[u8],
+    ) -> Result<(), ContractError> {
+        let verified = match self {
+            OracleKey::Secp256k1 { pubkey } => {
+                let hash = Sha256::digest(payload);
+                api.secp256k1_verify(&hash, signature, pubkey)
+            }
+            OracleKey::Ed25519 { pubkey } => api.ed25519_verify(payload, signature, pubkey),
+        };
+        match verified {
+            Ok(true) => Ok(()),
+            _ => Err(ContractError::InvalidSignature),
+        }
+    }
+}
+
+/// One oracle's value for a hex and time, waiting for the quorum
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Submission {
+    pub oracle: Addr,
+    pub value: Decimal,
+}
+
+/// The median of the values (the mean of the middle two for an even count), None if empty
+pub fn median(mut values: Vec<Decimal>) -> Option<Decimal> {
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    match values.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(values[mid]),
+        _ => {
+            let (low, high) = (values[mid - 1].atomics(), values[mid].atomics());
+            Some(from_atomics(low + (high - low) / Uint128::new(2)))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Investment {
+    // unique id, assigned in order of investment
+    pub id: u64,
+    // who owns (and can withdraw) this investment.
+    // the one who made it, until it is transferred as nft
+    pub investor: Addr,
+    // r3 index of the location invested in
+    pub hex: String,
+    // the token invested, which is also paid out
+    pub token: Denom,
     // how much was invested
     pub amount: Uint128,
     // starting value when investment was created
@@ -74,9 +559,23 @@ pub struct Investment {
     pub invested_time: u64,
     // when this investment can be claimed - in UNIX seconds UTC
     pub maturity_time: u64,
+    // who else may transfer this investment, cleared on transfer
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Approval {
+    pub spender: Addr,
+    pub expires: Expiration,
 }
 
 impl Investment {
+    /// the maturity tier this was invested in
+    pub fn maturity_days(&self) -> u64 {
+        (self.maturity_time - self.invested_time) / 86400
+    }
+
     /// whether or not this investment has reached maturity date and can be withdrawn
     pub fn is_mature(&self, env: &Env) -> bool {
         env.block.time.seconds() >= self.maturity_time
@@ -84,62 +583,176 @@ impl Investment {
 
     /// calculates what this would return if it was mature and the data was recent enough
     /// just for displaying in UI.
-    pub fn would_reward(&self, loc: &Location) -> Uint128 {
+    pub fn would_reward(&self, loc: &Location, cfg: &Config) -> Result<Uint128, ContractError> {
         if let Some(measure) = &loc.cur_index {
-            // measurement after maturity, within window
-            // calculate ratio, positive, if measurement below baseline
-            // no code to divide Decimals, so we do this
-            let ratio =
-                Decimal::from_ratio(self.baseline_index.numerator(), measure.value.numerator());
-            self.amount * ratio
+            self.payout_at(measure.value, cfg)
         } else {
-            Uint128::zero()
+            Ok(Uint128::zero())
         }
     }
 
+    /// payout if settled at the given index, following the payout curve
+    /// and capped at `cfg.max_payout` times the amount
+    fn payout_at(&self, index: Decimal, cfg: &Config) -> Result<Uint128, ContractError> {
+        // ratio grows, if measurement improved from the baseline
+        let ratio = cfg.index_direction.ratio(self.baseline_index, index);
+        let multiplier = cfg.payout_curve.multiplier(ratio)?.min(cfg.max_payout);
+        checked_mul(self.amount, multiplier)
+    }
+
     /// calculates the reward. if it is not mature, or there is insufficient data
-    /// to provide a result, then it will return None
-    pub fn reward(&self, env: &Env, loc: &Location, cfg: &Config) -> Option<Uint128> {
+    /// to provide a result, then it will return None.
+    /// only errors if the payout overflows
+    ///
+    /// `settlement` must be the first measurement taken at or after maturity
+    /// (see `measurement_after`), so the payout doesn't depend on when the investor withdraws
+    pub fn reward(
+        &self,
+        env: &Env,
+        settlement: Option<Measurement>,
+        cfg: &Config,
+    ) -> Result<Option<Uint128>, ContractError> {
         if !self.is_mature(env) {
-            return None;
+            return Ok(None);
         }
-        // TODO: we need to store historical data... you cannot just wait it out
-        if let Some(measure) = &loc.cur_index {
+        if let Some(measure) = settlement {
             match measure.time.checked_sub(self.maturity_time) {
                 Some(val) if val <= cfg.measurement_window * 86400 => {
                     // measurement after maturity, within window
-                    // calculate ratio, positive, if measurement below baseline
-                    // no code to divide Decimals, so we do this
-                    let ratio = Decimal::from_ratio(
-                        self.baseline_index.numerator(),
-                        measure.value.numerator(),
-                    );
-                    let reward = self.amount * ratio;
-                    Some(reward)
+                    self.payout_at(measure.value, cfg).map(Some)
                 }
                 Some(_) => {
                     // measurement after maturity, after window, return 100%
-                    Some(self.amount)
+                    Ok(Some(self.amount))
                 }
                 None => {
                     // measurement before maturity date
-                    None
+                    Ok(None)
                 }
             }
         } else {
-            None
+            Ok(None)
         }
     }
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
 pub const LOCATIONS: Map<&str, Location> = Map::new("locations");
-pub const INVESTMENTS: Map<(&Addr, &str), Vec<Investment>> = Map::new("investments");
+// which parts of the contract are paused
+pub const PAUSED: Item<PauseFlags> = Item::new("paused");
+// reserve ledger of each token, by denom_name
+pub const RESERVES: Map<&str, Reserve> = Map::new("reserves");
+// operators that may transfer all investments of an owner, by (owner, operator)
+pub const OPERATORS: Map<(&Addr, &Addr), Expiration> = Map::new("operators");
+// id of the last investment made, used to assign the next one
+pub const LAST_INVESTMENT_ID: Item<u64> = Item::new("last_investment_id");
+
+/// Returns a new unique id for an investment
+pub fn next_investment_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = LAST_INVESTMENT_ID.may_load(storage)?.unwrap_or_default() + 1;
+    LAST_INVESTMENT_ID.save(storage, &id)?;
+    Ok(id)
+}
+
+// number of open investments over all locations
+pub const OPEN_INVESTMENTS: Item<u64> = Item::new("open_investments");
+
+/// Adds `opened` and removes `closed` investments from the open ones, returning the new count
+pub fn count_investments(storage: &mut dyn Storage, opened: u64, closed: u64) -> StdResult<u64> {
+    let count = OPEN_INVESTMENTS.may_load(storage)?.unwrap_or_default() + opened;
+    let count = count
+        .checked_sub(closed)
+        .ok_or_else(|| OverflowError::new(OverflowOperation::Sub, count, closed))?;
+    OPEN_INVESTMENTS.save(storage, &count)?;
+    Ok(count)
+}
+
+pub struct InvestmentIndexes<'a> {
+    // all investments of one investor, in order of id
+    pub investor: MultiIndex<'a, (Addr, Vec<u8>), Investment>,
+    // all investments in one hex, in order of id
+    pub hex: MultiIndex<'a, (String, Vec<u8>), Investment>,
+    // all investments, ordered by the time they mature
+    pub maturity: MultiIndex<'a, (U64Key, Vec<u8>), Investment>,
+}
+
+impl<'a> IndexList<Investment> for InvestmentIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Investment>> + '_> {
+        let v: Vec<&dyn Index<Investment>> = vec![&self.investor, &self.hex, &self.maturity];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Open investments, stored one by one by id
+pub fn investments<'a>() -> IndexedMap<'a, U64Key, Investment, InvestmentIndexes<'a>> {
+    let indexes = InvestmentIndexes {
+        investor: MultiIndex::new(
+            |inv, pk| (inv.investor.clone(), pk),
+            "investments_by_id",
+            "investments__investor",
+        ),
+        hex: MultiIndex::new(
+            |inv, pk| (inv.hex.clone(), pk),
+            "investments_by_id",
+            "investments__hex",
+        ),
+        maturity: MultiIndex::new(
+            |inv, pk| (inv.maturity_time.into(), pk),
+            "investments_by_id",
+            "investments__maturity",
+        ),
+    };
+    IndexedMap::new("investments_by_id", indexes)
+}
+
+// all measurements ever stored for a hex, by (hex, unix time) -> value
+pub const MEASUREMENTS: Map<(&str, U64Key), Decimal> = Map::new("measurements");
+
+/// A new address an oracle proposed to take its place, which it must accept before `expires`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingOracle {
+    pub oracle: Addr,
+    pub expires: Expiration,
+}
+
+// proposed handovers, by the oracle that is replaced
+pub const PENDING_ORACLES: Map<&Addr, PendingOracle> = Map::new("pending_oracles");
+
+// keys the oracles sign relayed values with, by oracle address
+pub const ORACLE_KEYS: Map<&Addr, OracleKey> = Map::new("oracle_keys");
+
+// last nonce of the signed values relayed for each oracle. kept when the key changes or the
+// oracle is handed over, so old signatures never become valid again
+pub const ORACLE_NONCES: Map<&Addr, u64> = Map::new("oracle_nonces");
+
+// oracle values by (hex, time), buffered until the quorum is reached
+pub const SUBMISSIONS: Map<(&str, U64Key), Vec<Submission>> = Map::new("submissions");
+
+/// Returns the first measurement for this hex taken at or after `time`, if any.
+/// Investments are settled against this, rather than the latest value.
+pub fn measurement_after(
+    storage: &dyn Storage,
+    hex: &str,
+    time: u64,
+) -> StdResult<Option<Measurement>> {
+    MEASUREMENTS
+        .prefix_de(hex)
+        .range(
+            storage,
+            Some(Bound::inclusive_int(time)),
+            None,
+            Order::Ascending,
+        )
+        .next()
+        .transpose()
+        .map(|found| found.map(|(time, value)| Measurement::new(value, time)))
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm_std::testing::mock_env;
+    use crate::payout::PayoutStep;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
 
     fn env_at(secs: u64) -> Env {
         let mut env = mock_env();
@@ -151,59 +764,266 @@ mod test {
         mock_env().block.time.seconds() + secs
     }
 
-    fn loc_with_measurement(measure: Measurement) -> Location {
-        let mut loc = Location::new();
-        loc.cur_index = Some(measure);
-        loc
-    }
-
     #[test]
     fn investment_rewards() {
         let maturity_time = time_at(123 + 7 * 86400);
         let cfg = Config {
+            admin: None,
+            pauser: None,
             oracle: Addr::unchecked(""),
-            token: Addr::unchecked(""),
+            oracles: vec![],
+            quorum: 1,
+            oracle_guards: OracleGuards::default(),
+            resolutions: ResolutionRange::default(),
+            tokens: vec![Denom::Cw20(Addr::unchecked(""))],
             max_investment_hex: Uint128::new(1234567890123),
             maturity_days: 7,
+            maturity_tiers: vec![7],
             measurement_window: 2,
+            max_payout: Decimal::percent(200),
+            payout_curve: PayoutCurve::Linear {},
+            index_direction: IndexDirection::LowerIsBetter,
+            early_exit: None,
         };
         let invest = Investment {
+            id: 1,
+            investor: Addr::unchecked("investor"),
+            hex: "832830fffffffff".to_string(),
+            token: Denom::Cw20(Addr::unchecked("")),
             amount: Uint128::new(10000),
             baseline_index: Decimal::percent(450), // 4.5
             invested_time: time_at(123),
             maturity_time,
+            approvals: vec![],
         };
 
         // should get 1.5x payout
         let result = Decimal::percent(300);
-        let no_measure = Location::default();
-        let old_measurement = loc_with_measurement(Measurement::new(result, maturity_time - 1000));
-        let good_measurement =
-            loc_with_measurement(Measurement::new(result, maturity_time + 86400));
-        let late_measurement =
-            loc_with_measurement(Measurement::new(result, maturity_time + 3 * 86400));
+        let old_measurement = Some(Measurement::new(result, maturity_time - 1000));
+        let good_measurement = Some(Measurement::new(result, maturity_time + 86400));
+        let late_measurement = Some(Measurement::new(result, maturity_time + 3 * 86400));
 
         // env correct but no measurement
         let env = env_at(maturity_time + 2);
-        assert!(invest.reward(&env, &no_measure, &cfg).is_none());
+        assert!(invest.reward(&env, None, &cfg).unwrap().is_none());
 
         // env correct but old measurement
-        assert!(invest.reward(&env, &old_measurement, &cfg).is_none());
+        assert!(invest
+            .reward(&env, old_measurement, &cfg)
+            .unwrap()
+            .is_none());
 
         // env correct and good measurement -> 1.5x payout
         assert_eq!(
-            invest.reward(&env, &good_measurement, &cfg),
+            invest.reward(&env, good_measurement, &cfg).unwrap(),
             Some(Uint128::new(15000))
         );
 
         // env correct and late measurement -> 100% payout
         assert_eq!(
-            invest.reward(&env, &late_measurement, &cfg),
+            invest.reward(&env, late_measurement, &cfg).unwrap(),
             Some(Uint128::new(10000))
         );
 
         // measurement good, not yet mature, no payout (not sure how this happens...)
         let env = env_at(0);
-        assert!(invest.reward(&env, &good_measurement, &cfg).is_none());
+        assert!(invest
+            .reward(&env, good_measurement, &cfg)
+            .unwrap()
+            .is_none());
+
+        // index dropped to a tenth, payout capped at 2x
+        let env = env_at(maturity_time + 2);
+        let crash = Some(Measurement::new(
+            Decimal::percent(45),
+            maturity_time + 86400,
+        ));
+        assert_eq!(
+            invest.reward(&env, crash, &cfg).unwrap(),
+            Some(Uint128::new(20000))
+        );
+        // a zero index doesn't panic, but pays the max
+        let zero = Some(Measurement::new(Decimal::zero(), maturity_time + 86400));
+        assert_eq!(
+            invest.reward(&env, zero, &cfg).unwrap(),
+            Some(Uint128::new(20000))
+        );
+
+        // the payout curve can cap below max_payout
+        let capped = Config {
+            payout_curve: PayoutCurve::Capped {
+                min: Decimal::percent(80),
+                max: Decimal::percent(120),
+            },
+            ..cfg.clone()
+        };
+        assert_eq!(capped.max_multiplier(), Decimal::percent(120));
+        assert_eq!(
+            invest.reward(&env, good_measurement, &capped).unwrap(),
+            Some(Uint128::new(12000))
+        );
+
+        // if higher is better, the same drop loses a third
+        let higher = Config {
+            index_direction: IndexDirection::HigherIsBetter,
+            ..cfg.clone()
+        };
+        assert_eq!(
+            invest.reward(&env, good_measurement, &higher).unwrap(),
+            Some(Uint128::new(6666))
+        );
+        // and a rise pays
+        let rise = Some(Measurement::new(
+            Decimal::percent(675),
+            maturity_time + 86400,
+        ));
+        assert_eq!(
+            invest.reward(&env, rise, &higher).unwrap(),
+            Some(Uint128::new(15000))
+        );
+        let mut loc = Location::new();
+        loc.cur_index = rise;
+        assert_eq!(
+            invest.would_reward(&loc, &higher).unwrap(),
+            Uint128::new(15000)
+        );
+        assert_eq!(invest.would_reward(&loc, &cfg).unwrap(), Uint128::new(6666));
+    }
+
+    #[test]
+    fn early_exit_penalty() {
+        let exit = EarlyExit {
+            penalty: Decimal::percent(10),
+            fee_collector: None,
+        };
+        let invest = Investment {
+            id: 1,
+            investor: Addr::unchecked("investor"),
+            hex: "832830fffffffff".to_string(),
+            token: Denom::Cw20(Addr::unchecked("")),
+            amount: Uint128::new(10000),
+            baseline_index: Decimal::percent(450),
+            invested_time: time_at(0),
+            maturity_time: time_at(10 * 86400),
+            approvals: vec![],
+        };
+        let penalty = |secs| exit.penalty_for(&invest, &env_at(secs)).unwrap();
+        assert_eq!(penalty(0), Uint128::new(1000));
+        assert_eq!(penalty(86400), Uint128::new(900));
+        assert_eq!(penalty(5 * 86400), Uint128::new(500));
+        assert_eq!(penalty(10 * 86400), Uint128::zero());
+        assert_eq!(penalty(20 * 86400), Uint128::zero());
+    }
+
+    #[test]
+    fn reserve_settlement() {
+        let cfg = Config {
+            admin: None,
+            pauser: None,
+            oracle: Addr::unchecked(""),
+            oracles: vec![],
+            quorum: 1,
+            oracle_guards: OracleGuards::default(),
+            resolutions: ResolutionRange::default(),
+            tokens: vec![Denom::Cw20(Addr::unchecked("token"))],
+            max_investment_hex: Uint128::new(1234567890123),
+            maturity_days: 7,
+            maturity_tiers: vec![7],
+            measurement_window: 2,
+            max_payout: Decimal::percent(150),
+            payout_curve: PayoutCurve::Linear {},
+            index_direction: IndexDirection::LowerIsBetter,
+            early_exit: None,
+        };
+        let mut reserve = Reserve::new(Denom::Cw20(Addr::unchecked("token")));
+        reserve.invested = Uint128::new(1000);
+        reserve.reserve = Uint128::new(400);
+        assert_eq!(reserve.liabilities(&cfg), Uint128::new(500));
+        assert!(!reserve.is_solvent(&cfg));
+        // a lower cap on the curve means less liabilities
+        let capped = Config {
+            payout_curve: PayoutCurve::Step {
+                steps: vec![PayoutStep {
+                    ratio: Decimal::one(),
+                    multiplier: Decimal::percent(130),
+                }],
+            },
+            ..cfg.clone()
+        };
+        assert_eq!(reserve.liabilities(&capped), Uint128::new(300));
+
+        // a loss goes to the reserve
+        reserve
+            .settle(Uint128::new(200), Uint128::new(100))
+            .unwrap();
+        assert_eq!(reserve.invested, Uint128::new(800));
+        assert_eq!(reserve.reserve, Uint128::new(500));
+        assert!(reserve.is_solvent(&cfg));
+
+        // a gain is paid from it
+        reserve
+            .settle(Uint128::new(400), Uint128::new(600))
+            .unwrap();
+        assert_eq!(reserve.invested, Uint128::new(400));
+        assert_eq!(reserve.reserve, Uint128::new(300));
+
+        // but never more than there is
+        let err = reserve
+            .settle(Uint128::new(400), Uint128::new(800))
+            .unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve("token".to_string()));
+    }
+
+    #[test]
+    fn settle_on_first_measurement_after_maturity() {
+        let mut deps = mock_dependencies();
+        let hex = "832830fffffffff";
+        let other = "8928308280fffff";
+
+        assert_eq!(measurement_after(&deps.storage, hex, 1000).unwrap(), None);
+
+        for (time, value) in [(500u64, 1), (1000, 2), (1500, 3), (3000, 4)] {
+            MEASUREMENTS
+                .save(
+                    &mut deps.storage,
+                    (hex, time.into()),
+                    &Decimal::percent(value),
+                )
+                .unwrap();
+        }
+        MEASUREMENTS
+            .save(&mut deps.storage, (other, 1200.into()), &Decimal::one())
+            .unwrap();
+
+        // exact match is included
+        assert_eq!(
+            measurement_after(&deps.storage, hex, 1000).unwrap(),
+            Some(Measurement::new(Decimal::percent(2), 1000))
+        );
+        // otherwise the next one, ignoring other hexes
+        assert_eq!(
+            measurement_after(&deps.storage, hex, 1001).unwrap(),
+            Some(Measurement::new(Decimal::percent(3), 1500))
+        );
+        assert_eq!(
+            measurement_after(&deps.storage, hex, 2000).unwrap(),
+            Some(Measurement::new(Decimal::percent(4), 3000))
+        );
+        // nothing after the latest
+        assert_eq!(measurement_after(&deps.storage, hex, 3001).unwrap(), None);
+    }
+
+    #[test]
+    fn median_of_submissions() {
+        let pct = |vals: &[u64]| vals.iter().map(|v| Decimal::percent(*v)).collect();
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(pct(&[300])), Some(Decimal::percent(300)));
+        assert_eq!(median(pct(&[500, 100, 300])), Some(Decimal::percent(300)));
+        assert_eq!(
+            median(pct(&[400, 100, 200, 9000])),
+            Some(Decimal::percent(300))
+        );
+        // no overflow at the extremes
+        assert_eq!(median(vec![Decimal::MAX, Decimal::MAX]), Some(Decimal::MAX));
     }
 }